@@ -0,0 +1,104 @@
+//! `search_table` over a synthetic table of 20k random 768-dimension vectors, first as a full scan
+//! and then with the IVF-PQ index `build_indices` creates. Run with `cargo bench --bench search`.
+
+use std::sync::Arc;
+
+use arrow_array::{BooleanArray, FixedSizeListArray, Float32Array, RecordBatch, RecordBatchIterator, StringArray, TimestampMicrosecondArray};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use criterion::{criterion_group, criterion_main, Criterion};
+use shiroyaku::embedding::{self, EmbeddingType, Metric, RowFilter};
+
+const ROWS: usize = 20_000;
+const DIMENSION: usize = 768;
+const LIMIT: usize = 10;
+
+/// Deterministic pseudo-random unit vectors, flattened one after another
+fn unit_vectors(count: usize, seed: u32) -> Vec<f32> {
+    let mut state = seed;
+    let mut values = Vec::with_capacity(count * DIMENSION);
+    for _ in 0..count {
+        let mut vector: Vec<f32> = (0..DIMENSION)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+            })
+            .collect();
+        embedding::normalize(&mut vector);
+        values.extend(vector);
+    }
+    values
+}
+
+/// Rows laid out like a description table, with every column `search_table` reads
+fn synthetic_batch() -> RecordBatch {
+    let item = Arc::new(Field::new("item", DataType::Float32, true));
+    let vectors = FixedSizeListArray::try_new(item.clone(), DIMENSION as i32, Arc::new(Float32Array::from(unit_vectors(ROWS, 1))), None).unwrap();
+    let text = |prefix: &str| StringArray::from((0..ROWS).map(|i| format!("{} {}", prefix, i)).collect::<Vec<_>>());
+    let schema = Schema::new(vec![
+        Field::new("condition_name", DataType::Utf8, false),
+        Field::new("text", DataType::Utf8, false),
+        Field::new("groups", DataType::Utf8, false),
+        Field::new("medline_url", DataType::Utf8, false),
+        Field::new("sections", DataType::Utf8, false),
+        Field::new("boilerplate", DataType::Boolean, false),
+        Field::new("created_at", DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())), false),
+        Field::new("vector", DataType::FixedSizeList(item, DIMENSION as i32), false),
+    ]);
+    RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(text("Condition")),
+            Arc::new(text("Description of condition")),
+            Arc::new(StringArray::from(vec!["Synthetic"; ROWS])),
+            Arc::new(StringArray::from(vec![""; ROWS])),
+            Arc::new(StringArray::from(vec!["description"; ROWS])),
+            Arc::new(BooleanArray::from(vec![false; ROWS])),
+            Arc::new(TimestampMicrosecondArray::from(vec![0; ROWS]).with_timezone("UTC")),
+            Arc::new(vectors),
+        ],
+    )
+    .unwrap()
+}
+
+fn search(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let query = unit_vectors(1, 2);
+    
+    let (db, table) = runtime.block_on(async {
+        let db = embedding::open_db(dir.path()).await.unwrap();
+        let batch = synthetic_batch();
+        let schema = batch.schema();
+        let table = db
+            .create_table("description_embeddings", RecordBatchIterator::new([Ok(batch)], schema))
+            .execute()
+            .await
+            .unwrap();
+        (db, table)
+    });
+    
+    let mut group = c.benchmark_group("search_table_20k_x_768");
+    group.sample_size(20);
+    group.bench_function("full_scan", |b| {
+        b.to_async(&runtime).iter(|| async {
+            embedding::search_table(&table, EmbeddingType::Description, query.clone(), LIMIT, &RowFilter::default(), Metric::Cosine)
+                .await
+                .unwrap()
+        })
+    });
+    
+    // A fresh handle sees the index that was just built
+    runtime.block_on(embedding::build_indices(&db, true)).unwrap();
+    let table = runtime.block_on(embedding::get_table(&db, "description_embeddings")).unwrap();
+    group.bench_function("indexed", |b| {
+        b.to_async(&runtime).iter(|| async {
+            embedding::search_table(&table, EmbeddingType::Description, query.clone(), LIMIT, &RowFilter::default(), Metric::Cosine)
+                .await
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
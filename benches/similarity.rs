@@ -0,0 +1,41 @@
+//! Cosine similarity of one query against 10k 768-dimension vectors, the size of a full-scan search.
+//! Run with `cargo bench --bench similarity` and again with `--features simd` to compare the fast path.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use shiroyaku::embedding::{cosine_similarity, scalar_cosine_similarity};
+
+const VECTORS: usize = 10_000;
+const DIMENSION: usize = 768;
+
+/// Deterministic pseudo-random vectors so runs are comparable
+fn vectors(count: usize, seed: u32) -> Vec<Vec<f32>> {
+    let mut state = seed;
+    (0..count)
+        .map(|_| {
+            (0..DIMENSION)
+                .map(|_| {
+                    state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    (state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn similarity(c: &mut Criterion) {
+    let stored = vectors(VECTORS, 1);
+    let query = vectors(1, 2).remove(0);
+    
+    let mut group = c.benchmark_group("cosine_similarity_10k_x_768");
+    group.bench_function("scalar", |b| {
+        b.iter(|| stored.iter().map(|v| scalar_cosine_similarity(black_box(&query), v)).sum::<f32>())
+    });
+    // Same as scalar unless built with --features simd
+    group.bench_function("default", |b| {
+        b.iter(|| stored.iter().map(|v| cosine_similarity(black_box(&query), v)).sum::<f32>())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, similarity);
+criterion_main!(benches);
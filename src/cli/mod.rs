@@ -0,0 +1,968 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{Duration, Utc};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::fs;
+use std::io::IsTerminal;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tokio::task;
+
+use crate::embedding::{self, EmbeddingType, ExecutionProvider, Metric, ModelChoice, SearchResult};
+use crate::search::{self, AllowList, RankedCondition, SynonymMap, Weights};
+use crate::engine::validate_query;
+use crate::export::csv_field;
+use crate::fetch::{ConditionData, FetchError, TopicFilter};
+use crate::response::{BatchLine, ComparisonResponse, SearchResponse, StatsResponse, TableResponse};
+use crate::ui::NextAction;
+use crate::{export, fetch, history, saved, server, status, tui, ui, SearchEngine};
+
+/// Command-line options for running Shiroyaku from a terminal
+#[derive(Debug, Parser)]
+#[command(name = "shiroyaku", about = "MedlinePlus symptom search engine")]
+#[command(group(clap::ArgGroup::new("one_shot_query").args(["query", "query_file"])))]
+pub struct Cli {
+    /// Run the interactive search in the terminal instead of opening the window
+    #[arg(long)]
+    pub terminal: bool,
+    
+    /// Read settings from this TOML file instead of ./shiroyaku.toml; flags still override it
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    
+    /// Topic keywords from the config file's `[topics]` table
+    #[arg(skip)]
+    pub topics: TopicFilter,
+    
+    /// Show progress messages (-v), or step-by-step detail as well (-vv)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+    
+    /// Search in a full-screen view: pick results with the arrow keys and read their full text alongside
+    #[arg(long)]
+    pub tui: bool,
+    
+    /// Read symptoms over several lines, ending with a blank line or '.'
+    #[arg(long)]
+    pub multiline: bool,
+    
+    /// Drop the embeddings and rebuild them from the cached data without downloading
+    #[arg(long)]
+    pub rebuild: bool,
+    
+    /// Build an approximate (IVF_PQ) vector index on each embedding table
+    #[arg(long)]
+    pub build_index: bool,
+    
+    /// Skip fetching fresh MedlinePlus data and use existing embeddings
+    #[arg(long)]
+    pub no_update: bool,
+    
+    /// Number of candidates to pull from each embedding table
+    #[arg(long, default_value_t = 10, value_parser = at_least_one())]
+    pub top_k: usize,
+    
+    /// Number of ranked conditions to show
+    #[arg(long, default_value_t = 5, value_parser = at_least_one())]
+    pub num_results: usize,
+    
+    /// Number of results shown before asking whether to show more in the interactive search
+    #[arg(long, default_value_t = 5, value_parser = at_least_one())]
+    pub page_size: usize,
+    
+    /// Drop conditions whose combined score is below this value
+    #[arg(long, default_value_t = 0.0)]
+    pub min_score: f32,
+    
+    /// Only show conditions in a MedlinePlus group containing this text, e.g. "cancers"
+    #[arg(long)]
+    pub group: Option<String>,
+    
+    /// Only show conditions named in this file, one per line
+    #[arg(long)]
+    pub only_conditions: Option<PathBuf>,
+    
+    /// Only match embeddings created within this many days
+    #[arg(long, value_parser = at_least_one())]
+    pub embedded_within_days: Option<usize>,
+    
+    /// Strip filler like "I have" and normalize symptom wording before searching
+    #[arg(long)]
+    pub preprocess_query: bool,
+    
+    /// Rewrite everyday symptom phrases like "throwing up" into clinical terms before searching
+    #[arg(long)]
+    pub expand_synonyms: bool,
+    
+    /// Use this file of `phrase = replacement` lines instead of the bundled synonyms
+    #[arg(long, requires = "expand_synonyms")]
+    pub synonyms: Option<PathBuf>,
+    
+    /// After embedding, flag sections nearly identical to another condition's (shared boilerplate) and rank them lower
+    #[arg(long)]
+    pub dedup_sections: bool,
+    
+    /// Use this file of `abbreviation = condition name` lines instead of the bundled English aliases like "uti" and "copd"
+    #[arg(long)]
+    pub aliases: Option<PathBuf>,
+    
+    /// Show how each table contributed to every condition's score
+    #[arg(long)]
+    pub explain: bool,
+    
+    /// Print plain text without colors (also honors NO_COLOR)
+    #[arg(long)]
+    pub no_color: bool,
+    
+    /// Per-table scoring weights as desc,etio,manif[,treat] (default 1.0,1.2,1.5,0.8)
+    #[arg(long)]
+    pub weights: Option<String>,
+    
+    /// How query vectors are compared with the stored ones
+    #[arg(long, value_enum, default_value = "cosine")]
+    pub metric: Metric,
+    
+    /// How to print search results
+    #[arg(long, value_enum, default_value = "table")]
+    pub format: OutputFormat,
+    
+    /// Directory holding the MedlinePlus cache and embedding database
+    #[arg(long, default_value = "data")]
+    pub data_dir: PathBuf,
+    
+    /// MedlinePlus language to search: en or es
+    #[arg(long, default_value = "en")]
+    pub lang: String,
+    
+    /// Embedding model used to build and query the database
+    #[arg(long, value_enum, default_value = "gemma-300m")]
+    pub model: ModelChoice,
+    
+    /// Hardware to run the embedding model on; falls back to the CPU if it isn't available
+    #[arg(long, value_enum, default_value = "cpu")]
+    pub execution_provider: ExecutionProvider,
+    
+    /// Comma-separated title keywords that mark a MedlinePlus topic as a condition, replacing the configured list
+    #[arg(long, value_delimiter = ',')]
+    pub include_keywords: Option<Vec<String>>,
+    
+    /// Download MedlinePlus again once the cached data is this many days old; 0 refreshes every run
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(i64).range(0..))]
+    pub max_age_days: i64,
+    
+    /// How many times to retry a failed MedlinePlus request
+    #[arg(long, default_value_t = 3)]
+    pub fetch_retries: u32,
+    
+    /// Give up on downloading and embedding after this many seconds, searching the existing embeddings if there are any
+    #[arg(long)]
+    pub timeout_secs: Option<u64>,
+    
+    /// Run a single search for these symptoms and exit
+    #[arg(long)]
+    pub query: Option<String>,
+    
+    /// Run a single search for the whole text of this file, such as an intake note, and exit
+    #[arg(long, conflicts_with = "queries_file")]
+    pub query_file: Option<PathBuf>,
+    
+    /// Print the raw matches from only this section's table instead of the cross-referenced ranking
+    #[arg(long, value_enum, requires = "one_shot_query")]
+    pub table: Option<EmbeddingType>,
+    
+    /// Also search for these symptoms and show how the ranking differs from --query
+    #[arg(long, requires = "one_shot_query")]
+    pub compare: Option<String>,
+    
+    /// Run every line of this file as a query and print one JSON object per line
+    #[arg(long)]
+    pub queries_file: Option<PathBuf>,
+    
+    /// Print recent searches and exit
+    #[arg(long)]
+    pub history: bool,
+    
+    /// Write the cached MedlinePlus conditions to a .csv or .parquet file and exit
+    #[arg(long)]
+    pub export: Option<PathBuf>,
+    
+    /// Print a result set saved from an interactive search and exit
+    #[arg(long)]
+    pub load_results: Option<String>,
+    
+    /// Report what the embedding database holds and exit
+    #[arg(long)]
+    pub stats: bool,
+    
+    /// Search a few bundled synthetic conditions, kept in a `demo` folder of the data directory, without network access
+    #[arg(long)]
+    pub demo: bool,
+    
+    /// Parse, filter and split the MedlinePlus topics, print what would be embedded, and exit
+    #[arg(long)]
+    pub dry_run: bool,
+    
+    /// Don't record searches in the history file
+    #[arg(long)]
+    pub no_history: bool,
+    
+    /// Search with a JSON array of floats read from stdin instead of a text query
+    #[arg(long)]
+    pub embedding_stdin: bool,
+    
+    /// Serve searches over HTTP on this port instead of prompting
+    #[arg(long)]
+    pub serve: Option<u16>,
+}
+
+impl Cli {
+    /// Parse the command line, taking any of the config file's settings that no flag overrides
+    pub fn parse_with_config() -> Result<Self> {
+        let matches = Self::command().get_matches();
+        let mut cli = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        let config = Config::load(cli.config.as_deref())?;
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        
+        if let Some(data_dir) = config.data_dir.filter(|_| unset("data_dir")) {
+            cli.data_dir = data_dir;
+        }
+        if let Some(name) = config.model.filter(|_| unset("model")) {
+            cli.model = ModelChoice::from_str(&name, true).map_err(|_| anyhow!("Unknown model '{}' in the config file", name))?;
+        }
+        if let Some(lang) = config.lang.filter(|_| unset("lang")) {
+            cli.lang = lang;
+        }
+        if let Some(top_k) = config.top_k.filter(|_| unset("top_k")) {
+            cli.top_k = top_k;
+        }
+        if let Some(num_results) = config.num_results.filter(|_| unset("num_results")) {
+            cli.num_results = num_results;
+        }
+        if let Some(min_score) = config.min_score.filter(|_| unset("min_score")) {
+            cli.min_score = min_score;
+        }
+        if let Some(weights) = config.weights.filter(|_| unset("weights")) {
+            cli.weights = Some(weights);
+        }
+        cli.topics = config.topics;
+        
+        if cli.top_k == 0 || cli.num_results == 0 {
+            bail!("top_k and num_results in the config file must be at least 1");
+        }
+        Ok(cli)
+    }
+    
+    /// Check that the language is supported and the model can embed it
+    pub fn validate_language(&self) -> Result<()> {
+        fetch::topic_language(&self.lang)?;
+        if self.lang != "en" && !self.model.is_multilingual() {
+            bail!(
+                "--lang {} needs a multilingual model; use --model gemma-300m or --model multilingual-e5-small",
+                self.lang
+            );
+        }
+        Ok(())
+    }
+    
+    /// Per-table weights from --weights or the config file, or the defaults
+    pub fn search_weights(&self) -> Result<Weights> {
+        match self.weights.as_deref() {
+            Some(value) => value.parse(),
+            None => Ok(Weights::default()),
+        }
+    }
+    
+    /// Topic keywords from the config file, with --include-keywords applied
+    pub fn topic_filter(&self) -> TopicFilter {
+        let mut filter = self.topics.clone();
+        if let Some(keywords) = &self.include_keywords {
+            filter.include_keywords = keywords.iter().map(|k| k.trim().to_string()).filter(|k| !k.is_empty()).collect();
+        }
+        filter
+    }
+    
+    /// Whether the terminal search will prompt for symptoms rather than run one batch and exit
+    pub fn prompts_user(&self) -> bool {
+        self.query.is_none()
+            && self.query_file.is_none()
+            && self.queries_file.is_none()
+            && self.serve.is_none()
+            && !self.embedding_stdin
+            && std::io::stdin().is_terminal()
+    }
+    
+    /// Whether these options ask for the terminal front end rather than the window
+    pub fn runs_in_terminal(&self) -> bool {
+        self.terminal
+            || self.tui
+            || self.query.is_some()
+            || self.query_file.is_some()
+            || self.queries_file.is_some()
+            || self.serve.is_some()
+            || self.history
+            || self.load_results.is_some()
+            || self.export.is_some()
+            || self.stats
+            || self.dry_run
+            || self.demo
+            || self.embedding_stdin
+    }
+}
+
+/// Parser for counts that must be 1 or more
+fn at_least_one() -> clap::builder::RangedU64ValueParser<usize> {
+    clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+}
+
+/// Config file read from the current directory when --config isn't given
+const CONFIG_FILE: &str = "shiroyaku.toml";
+
+/// Settings a config file can provide; anything left out keeps its command-line default.
+/// Unknown keys are an error so a misspelled setting isn't silently ignored.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    data_dir: Option<PathBuf>,
+    model: Option<String>,
+    lang: Option<String>,
+    top_k: Option<usize>,
+    num_results: Option<usize>,
+    min_score: Option<f32>,
+    weights: Option<String>,
+    /// Which MedlinePlus topics count as conditions
+    topics: TopicFilter,
+}
+
+impl Config {
+    /// Read `path`, or `shiroyaku.toml` in the current directory when none is given.
+    /// A missing default file means no settings; a missing --config file is an error.
+    fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(CONFIG_FILE), false),
+        };
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Cannot read config file {}", path.display())),
+        };
+        toml::from_str(&content).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+}
+
+/// Exit status when there is no cached data or embeddings to search
+pub const EXIT_NO_DATA: i32 = 2;
+
+/// Exit status when MedlinePlus couldn't be downloaded and nothing was cached
+pub const EXIT_NETWORK: i32 = 3;
+
+/// Exit status when --timeout-secs runs out before there are any embeddings to search
+pub const EXIT_TIMEOUT: i32 = 4;
+
+/// Exit status when Ctrl-C stops an update, matching shells' 128 + SIGINT
+pub const EXIT_CANCELLED: i32 = 130;
+
+/// Failures that get their own exit status so scripts can tell them apart
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error("No embeddings found in the database; run without --no-update to build them")]
+    NoEmbeddings,
+    #[error("Update cancelled")]
+    Cancelled,
+    #[error("Updating the data took longer than {0}s and no embeddings are stored")]
+    TimedOut(u64),
+}
+
+/// Process exit status for an error returned by `run`
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<CliError>() {
+            return match e {
+                CliError::NoEmbeddings => EXIT_NO_DATA,
+                CliError::Cancelled => EXIT_CANCELLED,
+                CliError::TimedOut(_) => EXIT_TIMEOUT,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<FetchError>() {
+            return match e {
+                FetchError::NoCache => EXIT_NO_DATA,
+                FetchError::Http(_) | FetchError::NoRecentDump | FetchError::NotXml(_) | FetchError::Timeout(_) => EXIT_NETWORK,
+                _ => 1,
+            };
+        }
+    }
+    1
+}
+
+/// Output format for search results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Run the interactive terminal search
+pub async fn run(mut cli: Cli) -> Result<()> {
+    // The demo corpus gets its own cache and database so it never mixes with real data
+    if cli.demo {
+        cli.data_dir = cli.data_dir.join("demo");
+    }
+    
+    // A query file is searched exactly like --query with the file's text
+    if let Some(path) = cli.query_file.take() {
+        cli.query = Some(read_query_file(&path)?);
+    }
+    
+    if cli.history {
+        ui::init_color(cli.no_color);
+        history::display_history(&history::load(&cli.data_dir));
+        return Ok(());
+    }
+    
+    if let Some(name) = cli.load_results.as_deref() {
+        ui::init_color(cli.no_color);
+        ui::set_status_to_stderr(cli.format != OutputFormat::Table);
+        let saved = saved::load(&cli.data_dir, name)?;
+        let weights = cli.search_weights()?;
+        status!("\nResults saved as '{}' on {} for: {}", saved.name, saved.saved_at.get(..10).unwrap_or(&saved.saved_at), saved.query);
+        print_results(&saved.results, &saved.query, cli.format, &weights, cli.explain, None, None)?;
+        return Ok(());
+    }
+    
+    if cli.stats {
+        ui::init_color(cli.no_color);
+        let stats = embedding::database_stats(&cli.data_dir).await?;
+        match cli.format {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&StatsResponse::new(&stats))?),
+            _ => ui::display_stats(&stats),
+        }
+        return Ok(());
+    }
+    
+    // Dry runs stop before the model is loaded or any table is written
+    if cli.dry_run {
+        ui::init_color(cli.no_color);
+        cli.validate_language()?;
+        let filter = cli.topic_filter();
+        let dry_run = fetch::dry_run(&cli.data_dir, cli.no_update, cli.max_age_days, cli.fetch_retries, &cli.lang, &filter);
+        let Some(report) = until_ctrl_c(dry_run).await else {
+            eprintln!();
+            return Err(CliError::Cancelled.into());
+        };
+        let report = report?;
+        match cli.format {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+            _ => ui::display_dry_run(&report),
+        }
+        return Ok(());
+    }
+    
+    // Exports only read the cache, so they never touch the network or the model
+    if let Some(path) = cli.export.as_deref() {
+        cli.validate_language()?;
+        let conditions = fetch::load_conditions(&cli.data_dir, &cli.lang).with_context(|| {
+            format!(
+                "Cannot export without cached MedlinePlus data in {}; run once without --export first",
+                cli.data_dir.display()
+            )
+        })?;
+        export::export_conditions(&conditions, path)?;
+        status!("Exported {} conditions to {}", conditions.len(), path.display());
+        return Ok(());
+    }
+    
+    // Fail before loading the model if a one-shot query can't be searched
+    for query in cli.query.iter().chain(&cli.compare) {
+        validate_query(query)?;
+    }
+    
+    cli.validate_language()?;
+    
+    let weights = cli.search_weights()?;
+    
+    ui::init_color(cli.no_color);
+    
+    // Keep stdout clean for piping when printing machine-readable results
+    ui::set_status_to_stderr(cli.format != OutputFormat::Table || cli.queries_file.is_some());
+    
+    let one_shot = cli.query.is_some() || cli.embedding_stdin;
+    if cli.queries_file.is_none() && (!one_shot || cli.format == OutputFormat::Table) {
+        ui::display_welcome();
+    }
+    
+    ui::display_initializing();
+    // A precomputed query embedding needs neither the model nor an update, so the database is only read
+    if cli.embedding_stdin {
+        if !embedding::has_embeddings(&cli.data_dir, cli.model, &cli.lang).await {
+            return Err(CliError::NoEmbeddings.into());
+        }
+        let engine = configure_engine(SearchEngine::open_read_only(&cli.data_dir, cli.model).await?, &cli, weights);
+        let input = std::io::read_to_string(std::io::stdin())?;
+        let query_embedding: Vec<f32> = serde_json::from_str(&input)
+            .map_err(|e| anyhow!("Expected a JSON array of numbers on stdin: {}", e))?;
+        let results = engine.search_embedding(query_embedding, cli.num_results).await?;
+        print_results(&results, "", cli.format, engine.weights(), cli.explain, Some(engine.model_choice()), None)?;
+        return Ok(());
+    }
+    
+    let engine = configure_engine(SearchEngine::open(&cli.data_dir, cli.model, cli.execution_provider).await?, &cli, weights);
+    
+    // Tables left half-written by a cancelled or timed out update are rebuilt on the next run.
+    // Only this phase is timed; searches wait on the user.
+    let update = until_ctrl_c(update_embeddings(&cli, &engine));
+    let updated = match cli.timeout_secs {
+        Some(secs) => match tokio::time::timeout(std::time::Duration::from_secs(secs), update).await {
+            Ok(updated) => updated,
+            Err(_) if engine.has_embeddings().await => {
+                warn!("Updating the data took longer than {}s; searching the existing embeddings", secs);
+                Some(Ok(()))
+            }
+            Err(_) => return Err(CliError::TimedOut(secs).into()),
+        },
+        None => update.await,
+    };
+    let Some(updated) = updated else {
+        eprintln!();
+        return Err(CliError::Cancelled.into());
+    };
+    updated?;
+    
+    // The server reports missing embeddings through /health instead of exiting
+    if let Some(port) = cli.serve {
+        return server::serve(engine, port, cli.num_results).await;
+    }
+    
+    if !engine.has_embeddings().await {
+        return Err(CliError::NoEmbeddings.into());
+    }
+    
+    let largest_table = embedding::largest_table_rows(engine.db()).await?;
+    if cli.top_k > largest_table {
+        warn!(
+            "--top-k {} is more than the {} rows in the largest embedding table; every row will be compared",
+            cli.top_k, largest_table
+        );
+    }
+    
+    if let Some(path) = cli.queries_file.as_deref() {
+        return run_queries_file(&engine, path, cli.num_results).await;
+    }
+    
+    if let (Some(query), Some(table)) = (cli.query.as_deref(), cli.table) {
+        let results = engine.search_single_table(table, query).await?;
+        print_table_results(query, table, &results, cli.format, engine.model_choice())?;
+        return Ok(());
+    }
+    
+    if let (Some(query), Some(compare)) = (cli.query.as_deref(), cli.compare.as_deref()) {
+        let first = engine.search(query, cli.num_results).await?;
+        let second = engine.search(compare, cli.num_results).await?;
+        print_comparison(query, &first, compare, &second, cli.format, engine.model_choice())?;
+        return Ok(());
+    }
+    
+    if let Some(query) = cli.query.as_deref() {
+        let results = engine.search(query, cli.num_results).await?;
+        show_aliases(&cli, &engine, query);
+        print_results(&results, query, cli.format, engine.weights(), cli.explain, Some(engine.model_choice()), None)?;
+        return Ok(());
+    }
+    
+    if cli.tui {
+        if cli.prompts_user() && std::io::stdout().is_terminal() {
+            let cached = load_cached_conditions(&cli);
+            let history_dir = (!cli.no_history).then_some(cli.data_dir.as_path());
+            return tui::run(&engine, cli.num_results, history_dir, |result| condition_details(&cached, result)).await;
+        }
+        info!("--tui needs an interactive terminal; using the plain prompts");
+    }
+    
+    // Only page when someone is reading along; piped input would be consumed as answers
+    let page_size = (cli.prompts_user() && std::io::stdout().is_terminal()).then_some(cli.page_size);
+    // Full section text for "details", read from the cache the first time it is asked for
+    let mut cached_conditions: Option<Vec<ConditionData>> = None;
+    loop {
+        let multiline = cli.multiline;
+        let Some(input) = until_ctrl_c(task::spawn_blocking(move || ui::get_user_input(multiline))).await else {
+            break;
+        };
+        let input = input?;
+        if input.is_empty() || input.eq_ignore_ascii_case("q") {
+            break;
+        }
+        
+        if let Err(e) = validate_query(&input) {
+            status!("{}", e);
+            continue;
+        }
+        
+        let Some(results) = until_ctrl_c(engine.search_with_progress(&input, cli.num_results, &ui::display_table_hits)).await else {
+            break;
+        };
+        let mut results = results?;
+        show_aliases(&cli, &engine, &input);
+        print_results(&results, &input, cli.format, engine.weights(), cli.explain, Some(engine.model_choice()), page_size)?;
+        
+        if !cli.no_history {
+            if let Err(e) = history::append(&cli.data_dir, &input, &results) {
+                eprintln!("Could not save search history: {}", e);
+            }
+        }
+        
+        // Keep exploring "more like this" until the user asks for a new search or quits
+        let next = loop {
+            let shown = results.len();
+            let Some(next) = until_ctrl_c(task::spawn_blocking(move || ui::ask_next_action(shown))).await else {
+                break NextAction::Quit;
+            };
+            let number = match next? {
+                NextAction::MoreLike(number) => number,
+                NextAction::Save => {
+                    save_results(&cli.data_dir, &input, &results);
+                    continue;
+                }
+                NextAction::Details(number) => {
+                    let cached = cached_conditions.get_or_insert_with(|| load_cached_conditions(&cli));
+                    ui::display_details(&condition_details(cached, &results[number - 1]));
+                    continue;
+                }
+                next => break next,
+            };
+            
+            let name = results[number - 1].name.clone();
+            status!("\nConditions similar to {}:", ui::condition_name(&name));
+            let Some(similar) = until_ctrl_c(engine.search_similar(&name, cli.num_results)).await else {
+                break NextAction::Quit;
+            };
+            results = similar?;
+            print_results(&results, &input, cli.format, engine.weights(), cli.explain, Some(engine.model_choice()), page_size)?;
+        };
+        if next == NextAction::Quit {
+            break;
+        }
+    }
+    
+    status!("Goodbye! Take care!");
+    Ok(())
+}
+
+/// Cached MedlinePlus conditions for showing full section text, empty when the cache can't be read
+fn load_cached_conditions(cli: &Cli) -> Vec<ConditionData> {
+    fetch::load_conditions(&cli.data_dir, &cli.lang).unwrap_or_else(|e| {
+        warn!("Could not read cached conditions ({}); showing only the sections that matched", e);
+        Vec::new()
+    })
+}
+
+/// A result's full sections from the cache, or the sections it matched with when the cache lacks it
+fn condition_details(cached: &[ConditionData], result: &RankedCondition) -> ConditionData {
+    if let Some(condition) = cached.iter().find(|c| c.name == result.name) {
+        return condition.clone();
+    }
+    ConditionData {
+        name: result.name.clone(),
+        medline_url: result.medline_url.clone(),
+        groups: result.groups.clone(),
+        description: result.description_text.clone().unwrap_or_default(),
+        etiology: result.etiology_text.clone().unwrap_or_default(),
+        manifestations: result.manifestation_text.clone().unwrap_or_default(),
+        treatments: result.treatment_text.clone().unwrap_or_default(),
+    }
+}
+
+/// Read a whole file as one query, with line breaks and runs of whitespace collapsed to single spaces
+fn read_query_file(path: &Path) -> Result<String> {
+    let content = fs::read_to_string(path).with_context(|| format!("Could not read query file {}", path.display()))?;
+    let query = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    if query.is_empty() {
+        bail!("Query file {} is empty", path.display());
+    }
+    Ok(query)
+}
+
+/// Search for every non-empty line of a file, printing a JSON line per query and reporting failures per line
+async fn run_queries_file(engine: &SearchEngine, path: &Path, num_results: usize) -> Result<()> {
+    let content = std::fs::read_to_string(path).map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+    let lines: Vec<(usize, String)> = content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim().to_string()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+    let queries: Vec<String> = lines.iter().map(|(_, query)| query.clone()).collect();
+    
+    let mut failures = 0;
+    for ((line, query), results) in lines.iter().zip(engine.search_batch(&queries, num_results).await) {
+        let (results, error) = match results {
+            Ok(results) => (results, None),
+            Err(e) => {
+                failures += 1;
+                (Vec::new(), Some(e.to_string()))
+            }
+        };
+        let output = BatchLine {
+            line: *line,
+            response: SearchResponse::new(query, Some(engine.model_choice()), &results),
+            error,
+        };
+        println!("{}", serde_json::to_string(&output)?);
+    }
+    
+    info!("Ran {} queries ({} failed)", queries.len(), failures);
+    Ok(())
+}
+
+/// Ask for a name and save the results under it, checking before replacing a saved set
+fn save_results(data_dir: &Path, query: &str, results: &[RankedCondition]) {
+    let name = ui::ask_text("Name for these results:");
+    if name.is_empty() {
+        return;
+    }
+    
+    let saved = saved::exists(data_dir, &name).and_then(|exists| {
+        if exists && !ui::confirm(&format!("Results named '{}' already exist. Replace them?", name)) {
+            return Ok(None);
+        }
+        saved::save(data_dir, &name, query, results).map(Some)
+    });
+    match saved {
+        Ok(Some(path)) => status!("Saved to {}", path.display()),
+        Ok(None) => status!("Not saved."),
+        Err(e) => eprintln!("Could not save results: {}", e),
+    }
+}
+
+/// Apply the search settings from the command line and config file to an opened engine
+pub fn configure_engine(engine: SearchEngine, cli: &Cli, weights: Weights) -> SearchEngine {
+    engine
+        .with_language(cli.lang.clone())
+        .with_top_k(cli.top_k)
+        .with_min_score(cli.min_score)
+        .with_group(cli.group.clone())
+        .with_metric(cli.metric)
+        .with_allowed_conditions(cli.only_conditions.as_deref().and_then(AllowList::load))
+        .with_created_after(cli.embedded_within_days.map(|days| Utc::now() - Duration::days(days as i64)))
+        .with_preprocess_query(cli.preprocess_query)
+        .with_synonyms(cli.expand_synonyms.then(|| cli.synonyms.as_deref().and_then(SynonymMap::load).unwrap_or_default()))
+        .with_dedup_sections(cli.dedup_sections)
+        .with_aliases(cli.aliases.as_deref().and_then(SynonymMap::load))
+        .with_weights(weights)
+}
+
+/// Refresh, rebuild, or keep the stored embeddings as the options ask
+async fn update_embeddings(cli: &Cli, engine: &SearchEngine) -> Result<()> {
+    // A crash mid-write can leave a table that won't open; offer to start over from the cache
+    let mut rebuild = cli.rebuild;
+    if !rebuild {
+        if let Some(corrupt) = embedding::find_corrupt_table(engine.db()).await {
+            error!("{}", corrupt);
+            if !(cli.prompts_user() && ui::confirm("Delete the embedding tables and rebuild them from the cached data?")) {
+                return Err(corrupt.into());
+            }
+            rebuild = true;
+        }
+    }
+    
+    if rebuild {
+        ui::display_rebuilding_message();
+        let conditions = fetch::load_conditions(&cli.data_dir, &cli.lang)
+            .context("Cannot rebuild without cached data; run once without --rebuild first")?;
+        engine.rebuild(conditions).await?;
+    } else if cli.no_update {
+        ui::display_skipping_update();
+    } else if cli.demo {
+        if fetch::needs_fetch(&cli.data_dir, &cli.lang, cli.max_age_days) || !engine.has_embeddings().await {
+            let conditions = fetch::load_fixture(&cli.data_dir, &cli.lang, &cli.topic_filter())?;
+            ui::display_embedding_message();
+            engine.embed_conditions(conditions).await?;
+        }
+    } else if fetch::needs_fetch(&cli.data_dir, &cli.lang, cli.max_age_days) {
+        ui::display_fetching_message();
+        let filter = cli.topic_filter();
+        match fetch::fetch_conditions(&cli.data_dir, false, cli.fetch_retries, &cli.lang, &filter).await {
+            Ok(Some(conditions)) => {
+                ui::display_embedding_message();
+                engine.embed_conditions(conditions).await?;
+            }
+            // Same dump as last time, so only missing embeddings need building
+            Ok(None) if !engine.has_embeddings().await => {
+                ui::display_embedding_cached_message();
+                engine.embed_conditions(fetch::load_conditions(&cli.data_dir, &cli.lang)?).await?;
+            }
+            Ok(None) => ui::display_skipping_update(),
+            Err(e) => {
+                // With nothing to fall back on the fetch failure is the real error
+                if !engine.has_embeddings().await {
+                    return Err(e).context("Could not download MedlinePlus data and no embeddings are stored");
+                }
+                error!("Error fetching conditions: {}", e);
+                warn!("Falling back to existing embeddings");
+            }
+        }
+    } else if !engine.has_embeddings().await {
+        // The cached data is still fresh, so only the embeddings need rebuilding
+        ui::display_embedding_cached_message();
+        let conditions = fetch::load_conditions(&cli.data_dir, &cli.lang)?;
+        engine.embed_conditions(conditions).await?;
+    }
+    
+    if cli.build_index {
+        info!("Building vector indexes...");
+        engine.build_index().await?;
+    }
+    Ok(())
+}
+
+/// Under --explain, say which abbreviations in the query were searched as full condition names
+fn show_aliases(cli: &Cli, engine: &SearchEngine, query: &str) {
+    if !cli.explain {
+        return;
+    }
+    for (abbreviation, name) in engine.resolved_aliases(query) {
+        status!("Searched \"{}\" as \"{}\"", abbreviation, name);
+    }
+}
+
+/// Run a future to completion, or give up with `None` when Ctrl-C is pressed
+async fn until_ctrl_c<F: Future>(future: F) -> Option<F::Output> {
+    tokio::select! {
+        output = future => Some(output),
+        _ = tokio::signal::ctrl_c() => None,
+    }
+}
+
+/// Print search results in the requested format, pausing table output every `page_size` results when given
+pub fn print_results(
+    results: &[RankedCondition],
+    query: &str,
+    format: OutputFormat,
+    weights: &Weights,
+    explain: bool,
+    model: Option<ModelChoice>,
+    page_size: Option<usize>,
+) -> Result<()> {
+    match format {
+        OutputFormat::Table => search::display_results(results, query, weights, explain, page_size),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&SearchResponse::new(query, model, results))?),
+        OutputFormat::Csv => {
+            println!("name,score,match,groups,medline_url,description_matches,etiology_matches,manifestation_matches,treatment_matches");
+            for condition in results {
+                println!(
+                    "{},{:.4},{},{},{},{},{},{},{}",
+                    csv_field(&condition.name),
+                    condition.score,
+                    condition.match_kind,
+                    csv_field(&condition.groups.join("; ")),
+                    csv_field(&condition.medline_url),
+                    condition.description_matches,
+                    condition.etiology_matches,
+                    condition.manifestation_matches,
+                    condition.treatment_matches
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print one table's raw matches in the requested format
+pub fn print_table_results(
+    query: &str,
+    table: EmbeddingType,
+    results: &[SearchResult],
+    format: OutputFormat,
+    model: ModelChoice,
+) -> Result<()> {
+    match format {
+        OutputFormat::Table => search::display_table_results(table.as_str(), results),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&TableResponse::new(query, table, Some(model), results))?),
+        OutputFormat::Csv => {
+            println!("rank,name,score,table,text");
+            for (i, result) in results.iter().enumerate() {
+                println!(
+                    "{},{},{:.4},{},{}",
+                    i + 1,
+                    csv_field(&result.condition_name),
+                    result.score,
+                    table,
+                    csv_field(&result.text)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print how the ranked conditions differ between two searches in the requested format
+pub fn print_comparison(
+    first_query: &str,
+    first: &[RankedCondition],
+    second_query: &str,
+    second: &[RankedCondition],
+    format: OutputFormat,
+    model: ModelChoice,
+) -> Result<()> {
+    let compared = search::compare_results(first, second);
+    match format {
+        OutputFormat::Table => search::display_comparison(first_query, second_query, &compared),
+        OutputFormat::Json => {
+            let output = ComparisonResponse::new(Some(model), (first_query, first), (second_query, second), compared);
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+        OutputFormat::Csv => {
+            println!("name,first_rank,second_rank,first_score,second_score,change");
+            for c in &compared {
+                println!(
+                    "{},{},{},{},{},{}",
+                    csv_field(&c.name),
+                    c.first_rank.map_or(String::new(), |r| r.to_string()),
+                    c.second_rank.map_or(String::new(), |r| r.to_string()),
+                    c.first_score.map_or(String::new(), |s| format!("{:.4}", s)),
+                    c.second_score.map_or(String::new(), |s| format!("{:.4}", s)),
+                    c.change
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn load(content: &str) -> Result<Config> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        fs::write(&path, content).unwrap();
+        Config::load(Some(&path))
+    }
+    
+    #[test]
+    fn search_settings_and_topics_come_from_one_file() {
+        let config = load("top_k = 20\nmodel = \"bge-small\"\n\n[topics]\ninclude_keywords = [\"allergy\"]\nstrict = true\n").unwrap();
+        assert_eq!(config.top_k, Some(20));
+        assert_eq!(config.model.as_deref(), Some("bge-small"));
+        assert_eq!(config.topics.include_keywords, ["allergy"]);
+        assert!(config.topics.strict);
+        // Topic settings left out keep their defaults
+        assert_eq!(config.topics.min_conditions, TopicFilter::default().min_conditions);
+    }
+    
+    #[test]
+    fn misspelled_keys_are_rejected() {
+        let error = load("num_result = 5\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("unknown field `num_result`"), "{:#}", error);
+        let error = load("[topics]\ninclude_keyword = [\"allergy\"]\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("unknown field `include_keyword`"), "{:#}", error);
+    }
+    
+    #[test]
+    fn missing_config_path_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Config::load(Some(&dir.path().join("absent.toml"))).is_err());
+    }
+}
@@ -1,35 +1,574 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use arrow_array::cast::AsArray;
-use arrow_array::{RecordBatch, RecordBatchIterator, StringArray};
+use arrow_array::types::{Float32Type, TimestampMicrosecondType};
+use arrow_array::{BooleanArray, FixedSizeListArray, Float32Array, RecordBatch, RecordBatchIterator, StringArray, TimestampMicrosecondArray};
+use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
-use lancedb::connect;
-use lancedb::query::{ExecutableQuery};
+use lancedb::index::vector::IvfPqIndexBuilder;
+use lancedb::index::Index;
+use lancedb::query::{ExecutableQuery, QueryBase, Select};
+use lancedb::{connect, DistanceType};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::oneshot;
 
-use crate::fetch::ConditionData;
+use crate::fetch::{is_meaningful, stable_hash, ConditionData};
+use crate::status;
 
-const DB_PATH: &str = "data/lancedb";
+/// Number of texts fastembed runs through the model at once
+const EMBED_BATCH_SIZE: usize = 64;
+
+/// Sections embedded and written to a table at a time, bounding how many vectors are held in memory
+const WRITE_CHUNK_SIZE: usize = 256;
+
+/// Embedding models that can be selected with --model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ModelChoice {
+    /// EmbeddingGemma 300M
+    #[default]
+    #[value(name = "gemma-300m")]
+    Gemma300M,
+    /// BGE small English v1.5
+    #[value(name = "bge-small")]
+    BgeSmall,
+    /// BGE base English v1.5
+    #[value(name = "bge-base")]
+    BgeBase,
+    /// all-MiniLM-L6-v2
+    #[value(name = "minilm")]
+    MiniLm,
+    /// Multilingual E5 small
+    #[value(name = "multilingual-e5-small")]
+    MultilingualE5Small,
+}
+
+impl ModelChoice {
+    /// The fastembed model this choice loads
+    pub fn embedding_model(self) -> fastembed::EmbeddingModel {
+        match self {
+            ModelChoice::Gemma300M => fastembed::EmbeddingModel::EmbeddingGemma300M,
+            ModelChoice::BgeSmall => fastembed::EmbeddingModel::BGESmallENV15,
+            ModelChoice::BgeBase => fastembed::EmbeddingModel::BGEBaseENV15,
+            ModelChoice::MiniLm => fastembed::EmbeddingModel::AllMiniLML6V2,
+            ModelChoice::MultilingualE5Small => fastembed::EmbeddingModel::MultilingualE5Small,
+        }
+    }
+    
+    /// Rough size of the model files fastembed downloads, in MB
+    pub fn approx_download_mb(self) -> u32 {
+        match self {
+            ModelChoice::Gemma300M => 1200,
+            ModelChoice::BgeSmall => 130,
+            ModelChoice::BgeBase => 440,
+            ModelChoice::MiniLm => 90,
+            ModelChoice::MultilingualE5Small => 470,
+        }
+    }
+    
+    /// Text put before a search query, as the model was trained with
+    pub fn query_prefix(self) -> &'static str {
+        match self {
+            ModelChoice::Gemma300M => "task: search result | query: ",
+            ModelChoice::BgeSmall | ModelChoice::BgeBase => "Represent this sentence for searching relevant passages: ",
+            ModelChoice::MiniLm => "",
+            ModelChoice::MultilingualE5Small => "query: ",
+        }
+    }
+    
+    /// Text put before a stored section, as the model was trained with
+    pub fn passage_prefix(self) -> &'static str {
+        match self {
+            ModelChoice::Gemma300M => "title: none | text: ",
+            ModelChoice::BgeSmall | ModelChoice::BgeBase | ModelChoice::MiniLm => "",
+            ModelChoice::MultilingualE5Small => "passage: ",
+        }
+    }
+    
+    /// Whether the model handles languages other than English
+    pub fn is_multilingual(self) -> bool {
+        matches!(self, ModelChoice::Gemma300M | ModelChoice::MultilingualE5Small)
+    }
+}
+
+/// ONNX Runtime backends that can be selected with --execution-provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ExecutionProvider {
+    /// Run on the CPU
+    #[default]
+    Cpu,
+    /// NVIDIA GPUs through CUDA (needs the `cuda` feature)
+    Cuda,
+    /// Apple hardware through Core ML (needs the `coreml` feature)
+    Coreml,
+}
+
+impl std::fmt::Display for ExecutionProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionProvider::Cpu => write!(f, "cpu"),
+            ExecutionProvider::Cuda => write!(f, "cuda"),
+            ExecutionProvider::Coreml => write!(f, "coreml"),
+        }
+    }
+}
+
+impl ExecutionProvider {
+    /// ONNX Runtime providers to register, empty when the model should run on the CPU
+    fn dispatch(self) -> Vec<fastembed::ExecutionProviderDispatch> {
+        match self {
+            ExecutionProvider::Cpu => Vec::new(),
+            #[cfg(feature = "cuda")]
+            ExecutionProvider::Cuda => {
+                use ort::execution_providers::ExecutionProvider as _;
+                let provider = ort::execution_providers::CUDA::default();
+                if provider.is_available().unwrap_or(false) {
+                    vec![provider.build()]
+                } else {
+                    warn!("CUDA is not available in this ONNX Runtime, falling back to CPU");
+                    Vec::new()
+                }
+            }
+            #[cfg(feature = "coreml")]
+            ExecutionProvider::Coreml => {
+                use ort::execution_providers::ExecutionProvider as _;
+                let provider = ort::execution_providers::CoreML::default();
+                if provider.is_available().unwrap_or(false) {
+                    vec![provider.build()]
+                } else {
+                    warn!("Core ML is not available in this ONNX Runtime, falling back to CPU");
+                    Vec::new()
+                }
+            }
+            #[allow(unreachable_patterns)]
+            other => {
+                warn!("Built without the `{}` feature, falling back to CPU", other);
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// Fewest rows IVF_PQ can train its codebooks on
+const MIN_INDEX_ROWS: usize = 256;
+
+/// Row count at which a table is indexed automatically after embedding
+const AUTO_INDEX_ROWS: usize = 5000;
+
+/// Marker file recording how the tables in the database were built
+const VERSION_FILE: &str = ".shiroyaku_version.json";
+
+/// On-disk table layout version, bumped whenever the stored columns or index distance change
+const STORAGE_VERSION: u32 = 10;
+
+/// Similarity at or above which sections of two different conditions count as the same boilerplate text
+const BOILERPLATE_SIMILARITY: f32 = 0.97;
+
+/// Amount taken off the score of a section flagged as boilerplate. Subtracting lowers every score,
+/// negative ones included, where scaling would raise a negative score toward zero.
+const BOILERPLATE_PENALTY: f32 = 0.25;
+
+/// Separator between MedlinePlus group names in the `groups` column
+pub const GROUP_SEPARATOR: &str = "; ";
+
+/// Records which model and layout an embedding database was built with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VersionMarker {
+    pub storage_version: u32,
+    pub model: String,
+    pub dimension: usize,
+    #[serde(default = "default_language")]
+    pub language: String,
+    /// Vectors are stored at unit length, so dot product equals cosine similarity
+    #[serde(default)]
+    pub normalized: bool,
+}
+
+/// Language of databases built before the language was recorded
+fn default_language() -> String {
+    "en".to_string()
+}
+
+impl VersionMarker {
+    /// Marker for tables built by this version of the program with the given model and language
+    pub fn current(model: ModelChoice, language: &str) -> Result<Self> {
+        let embedding_model = model.embedding_model();
+        let info = fastembed::TextEmbedding::get_model_info(&embedding_model)?;
+        Ok(Self {
+            storage_version: STORAGE_VERSION,
+            model: embedding_model.to_string(),
+            dimension: info.dim,
+            language: language.to_string(),
+            normalized: true,
+        })
+    }
+}
+
+/// How query vectors are compared with stored vectors, selected with --metric. Each searches with
+/// its own LanceDB distance, so on vectors of different lengths they rank differently: cosine looks
+/// only at the angle, dot also favours long vectors, and Euclidean favours vectors ending near the
+/// query. Stored vectors are unit length, which makes the three agree on the tables built here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Metric {
+    /// Angle between the vectors, ignoring their length
+    #[default]
+    Cosine,
+    /// Dot product with the query left at its original length
+    Dot,
+    /// Straight-line distance between the vectors, ranked nearest first
+    Euclidean,
+}
+
+/// Metric the vector indexes are built for; searches by another metric scan the table instead
+const INDEX_METRIC: Metric = Metric::Cosine;
+
+impl Metric {
+    /// Whether the query is scaled to unit length before searching, like the stored vectors
+    pub fn normalizes_query(self) -> bool {
+        self != Metric::Dot
+    }
+    
+    /// Distance LanceDB searches and indexes with for this metric
+    const fn distance_type(self) -> DistanceType {
+        match self {
+            Metric::Cosine => DistanceType::Cosine,
+            Metric::Dot => DistanceType::Dot,
+            Metric::Euclidean => DistanceType::L2,
+        }
+    }
+    
+    /// Similarity score, higher is better, for a distance LanceDB returned under this metric
+    fn score(self, distance: f32) -> f32 {
+        match self {
+            // Cosine distance is 1 - cosine similarity, and dot distance 1 - dot product
+            Metric::Cosine | Metric::Dot => 1.0 - distance,
+            // L2 distance comes squared; halving its root puts unit vectors between 0 (opposite) and 1 (identical)
+            Metric::Euclidean => 1.0 - distance.max(0.0).sqrt() / 2.0,
+        }
+    }
+}
+
+/// An embedding table that is on disk but can't be opened or read, e.g. after a crash mid-write
+#[derive(Debug, thiserror::Error)]
+#[error("The embedding database appears corrupt ({table}: {reason}); re-run with --rebuild to recreate it")]
+pub struct CorruptTable {
+    pub table: String,
+    pub reason: String,
+}
+
+impl CorruptTable {
+    /// Corruption of `table` described by the error that reading it raised
+    pub fn new(table: &str, error: impl std::fmt::Display) -> Self {
+        Self {
+            table: table.to_string(),
+            reason: error.to_string(),
+        }
+    }
+}
+
+/// Section of a topic that an embedding table holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingType {
+    Description,
+    Etiology,
+    Manifestation,
+    Treatment,
+}
+
+impl std::fmt::Display for EmbeddingType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl EmbeddingType {
+    /// Section name as used in `SECTIONS` and table names
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EmbeddingType::Description => "description",
+            EmbeddingType::Etiology => "etiology",
+            EmbeddingType::Manifestation => "manifestation",
+            EmbeddingType::Treatment => "treatment",
+        }
+    }
+    
+    /// Name of the LanceDB table holding this section's embeddings
+    pub fn table_name(self) -> String {
+        format!("{}_embeddings", self.as_str())
+    }
+    
+    /// Section stored in a `<section>_embeddings` table, if the name is one
+    pub fn from_table_name(name: &str) -> Option<Self> {
+        match name.strip_suffix("_embeddings")? {
+            "description" => Some(EmbeddingType::Description),
+            "etiology" => Some(EmbeddingType::Etiology),
+            "manifestation" => Some(EmbeddingType::Manifestation),
+            "treatment" => Some(EmbeddingType::Treatment),
+            _ => None,
+        }
+    }
+}
+
+/// Which sections of a condition had meaningful text when it was embedded, stored on each of its rows
+/// so a section that didn't match can be told apart from one there was nothing to match against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectionPresence {
+    pub description: bool,
+    pub etiology: bool,
+    pub manifestation: bool,
+    pub treatment: bool,
+}
+
+/// Every section present, as assumed for results saved before presence was recorded
+impl Default for SectionPresence {
+    fn default() -> Self {
+        Self { description: true, etiology: true, manifestation: true, treatment: true }
+    }
+}
+
+impl SectionPresence {
+    /// Sections of a condition that are embedded
+    pub fn of(condition: &ConditionData) -> Self {
+        Self {
+            description: is_meaningful(&condition.description),
+            etiology: is_meaningful(&condition.etiology),
+            manifestation: is_meaningful(&condition.manifestations),
+            treatment: is_meaningful(&condition.treatments),
+        }
+    }
+    
+    /// Whether the condition has text for this section
+    pub fn has(self, section: EmbeddingType) -> bool {
+        match section {
+            EmbeddingType::Description => self.description,
+            EmbeddingType::Etiology => self.etiology,
+            EmbeddingType::Manifestation => self.manifestation,
+            EmbeddingType::Treatment => self.treatment,
+        }
+    }
+    
+    /// Value of the `sections` column: the present section names separated by commas
+    fn to_column(self) -> String {
+        SECTIONS
+            .into_iter()
+            .zip([self.description, self.etiology, self.manifestation, self.treatment])
+            .filter(|(_, present)| *present)
+            .map(|(section, _)| section)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+    
+    /// Parse a stored `sections` value
+    fn from_column(value: &str) -> Self {
+        let sections: Vec<&str> = value.split(',').collect();
+        Self {
+            description: sections.contains(&"description"),
+            etiology: sections.contains(&"etiology"),
+            manifestation: sections.contains(&"manifestation"),
+            treatment: sections.contains(&"treatment"),
+        }
+    }
+}
 
 /// Search result from a single table
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct SearchResult {
     pub condition_name: String,
     pub text: String,
-    pub embedding_type: String,
+    pub embedding_type: EmbeddingType,
+    pub sections: SectionPresence,
+    pub groups: Vec<String>,
+    pub medline_url: String,
+    pub score: f32,
+}
+
+/// Directory the embedding models are downloaded to, unless `HF_HOME` points elsewhere
+pub fn model_cache_dir(data_dir: &Path) -> PathBuf {
+    std::env::var_os("HF_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| data_dir.join("models"))
+}
+
+/// Check whether the model's files have already been downloaded to the cache
+fn model_is_cached(cache_dir: &Path, model: ModelChoice) -> Result<bool> {
+    let embedding_model = model.embedding_model();
+    let info = fastembed::TextEmbedding::get_model_info(&embedding_model)?;
+    let repo_dir = format!("models--{}", info.model_code.replace('/', "--"));
+    Ok(cache_dir.join(repo_dir).join("snapshots").is_dir())
+}
+
+/// Load the text embedding model used for both conditions and queries, downloading it on first use
+pub fn load_model(model: ModelChoice, provider: ExecutionProvider, data_dir: &Path) -> Result<fastembed::TextEmbedding> {
+    let cache_dir = model_cache_dir(data_dir);
+    info!("Model cache: {}", cache_dir.display());
+    if !model_is_cached(&cache_dir, model)? {
+        status!(
+            "Downloading embedding model {} (~{} MB) to {}; this only happens once",
+            model.embedding_model(),
+            model.approx_download_mb(),
+            cache_dir.display()
+        );
+    }
+    
+    let model = fastembed::TextEmbedding::try_new(
+        fastembed::InitOptions::new(model.embedding_model())
+            .with_cache_dir(cache_dir)
+            .with_execution_providers(provider.dispatch()),
+    )?;
+    Ok(model)
+}
+
+/// Turns a batch of texts into vectors on the embedding thread
+pub trait Embed: Send + 'static {
+    fn embed_texts(&mut self, texts: Vec<String>) -> Result<Vec<Vec<f32>>>;
+}
+
+impl Embed for fastembed::TextEmbedding {
+    fn embed_texts(&mut self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.embed(texts, Some(EMBED_BATCH_SIZE))
+    }
+}
+
+/// Work for the embedding thread: texts to embed and where to send their vectors
+type EmbedJob = (Vec<String>, oneshot::Sender<Result<Vec<Vec<f32>>>>);
+
+/// Handle to a thread that owns the loaded model, so concurrent callers share it one batch at a time
+#[derive(Debug, Clone)]
+pub struct EmbedderHandle {
+    jobs: std::sync::mpsc::Sender<EmbedJob>,
+}
+
+impl EmbedderHandle {
+    /// Move the model onto its own thread; the thread exits once every handle is dropped
+    pub fn spawn(mut model: impl Embed) -> Result<Self> {
+        let (jobs, queue) = std::sync::mpsc::channel::<EmbedJob>();
+        std::thread::Builder::new()
+            .name("embedder".to_string())
+            .spawn(move || {
+                for (texts, reply) in queue {
+                    // The caller may have given up waiting, which is fine
+                    let _ = reply.send(model.embed_texts(texts));
+                }
+            })?;
+        Ok(Self { jobs })
+    }
+    
+    /// Embed one text
+    pub async fn embed(&self, text: String) -> Result<Vec<f32>> {
+        self.embed_batch(vec![text])
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow!("Embedding model returned no vector"))
+    }
+    
+    /// Embed several texts in one job, keeping their order
+    pub async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let (reply, response) = oneshot::channel();
+        self.jobs
+            .send((texts, reply))
+            .map_err(|_| anyhow!("Embedding thread has stopped"))?;
+        response.await.map_err(|_| anyhow!("Embedding thread has stopped"))?
+    }
 }
 
-/// Embed and store condition data in LanceDB
+/// Embed and store condition data in LanceDB, only re-embedding conditions that changed.
+/// Returns the names of the conditions that were embedded.
 pub async fn embed_conditions(
+    data_dir: &Path,
     conditions: Vec<ConditionData>,
-    model: &mut fastembed::TextEmbedding,
-) -> Result<()> {
+    model: &EmbedderHandle,
+    model_choice: ModelChoice,
+    language: &str,
+) -> Result<Vec<String>> {
     if conditions.is_empty() {
-        println!("No conditions to embed");
-        return Ok(());
+        info!("No conditions to embed");
+        return Ok(Vec::new());
+    }
+    
+    let db = open_db(data_dir).await?;
+    // Every table must hold vectors of the model's dimension so they can be cross-referenced
+    let dimension = VersionMarker::current(model_choice, language)?.dimension;
+    
+    // Tables built by another model or layout can't be appended to
+    let stored_hashes = if check_version(data_dir, model_choice, language).is_ok() {
+        stored_content_hashes(&db).await?
+    } else {
+        drop_embedding_tables(&db).await?;
+        HashMap::new()
+    };
+    
+    let fresh_hashes: HashMap<&str, String> = conditions
+        .iter()
+        .map(|c| (c.name.as_str(), content_hash(c)))
+        .collect();
+    
+    // Rows for conditions that changed or were dropped from MedlinePlus are replaced
+    let stale: Vec<&str> = stored_hashes
+        .iter()
+        .filter(|(name, hash)| fresh_hashes.get(name.as_str()) != Some(*hash))
+        .map(|(name, _)| name.as_str())
+        .collect();
+    let conditions: Vec<&ConditionData> = conditions
+        .iter()
+        .filter(|c| stored_hashes.get(&c.name) != fresh_hashes.get(c.name.as_str()))
+        .collect();
+    
+    // Without a marker an interrupted run is rebuilt from scratch next time
+    remove_version_marker(data_dir)?;
+    
+    if !stale.is_empty() {
+        info!("Removing {} outdated conditions...", stale.len());
+        delete_conditions(&db, &stale).await?;
+    }
+    
+    if conditions.is_empty() {
+        write_version_marker(data_dir, model_choice, language)?;
+        info!("Embeddings are up to date");
+        return Ok(Vec::new());
+    }
+    
+    let mut description_sections: Vec<(&ConditionData, &str)> = Vec::new();
+    let mut etiology_sections: Vec<(&ConditionData, &str)> = Vec::new();
+    let mut manifestation_sections: Vec<(&ConditionData, &str)> = Vec::new();
+    let mut treatment_sections: Vec<(&ConditionData, &str)> = Vec::new();
+    
+    let mut without_text = 0;
+    for &condition in &conditions {
+        let sections = [&condition.description, &condition.etiology, &condition.manifestations, &condition.treatments];
+        if !sections.iter().any(|s| is_meaningful(s)) {
+            without_text += 1;
+        }
+        
+        if is_meaningful(&condition.description) {
+            description_sections.push((condition, condition.description.as_str()));
+        }
+        
+        if is_meaningful(&condition.etiology) {
+            etiology_sections.push((condition, condition.etiology.as_str()));
+        }
+        
+        if is_meaningful(&condition.manifestations) {
+            manifestation_sections.push((condition, condition.manifestations.as_str()));
+        }
+        
+        if is_meaningful(&condition.treatments) {
+            treatment_sections.push((condition, condition.treatments.as_str()));
+        }
+    }
+    
+    if without_text > 0 {
+        info!("{} conditions have no section text to embed", without_text);
     }
     
-    let total = conditions.len() * 3;
-    println!("Embedding {} conditions ({} total embeddings)...", conditions.len(), total);
+    // Empty sections are never embedded, so only the sections with text count toward the total
+    let total = description_sections.len() + etiology_sections.len() + manifestation_sections.len() + treatment_sections.len();
+    info!("Embedding {} conditions ({} total embeddings)...", conditions.len(), total);
+    debug!("Embedding dimension: {}", dimension);
     
     use indicatif::{ProgressBar, ProgressStyle};
     let pb = ProgressBar::new(total as u64);
@@ -41,92 +580,232 @@ pub async fn embed_conditions(
     );
     pb.set_message("Embedding conditions...");
     
-    let mut description_data: Vec<(&str, &str, Vec<f32>)> = Vec::new();
-    let mut etiology_data: Vec<(&str, &str, Vec<f32>)> = Vec::new();
-    let mut manifestation_data: Vec<(&str, &str, Vec<f32>)> = Vec::new();
+    let tables = SECTIONS.into_iter().zip([description_sections, etiology_sections, manifestation_sections, treatment_sections]);
     
-    for condition in &conditions {
-        if !condition.description.is_empty() && condition.description != "No summary available" {
-            let emb = model.embed(vec![condition.description.clone()], None)?;
-            description_data.push((condition.name.as_str(), condition.description.as_str(), emb[0].clone()));
-            pb.inc(1);
-        }
-        
-        if !condition.etiology.is_empty() && condition.etiology != "N/A" {
-            let emb = model.embed(vec![condition.etiology.clone()], None)?;
-            etiology_data.push((condition.name.as_str(), condition.etiology.as_str(), emb[0].clone()));
-            pb.inc(1);
-        }
-        
-        if !condition.manifestations.is_empty() && condition.manifestations != "N/A" {
-            let emb = model.embed(vec![condition.manifestations.clone()], None)?;
-            manifestation_data.push((condition.name.as_str(), condition.manifestations.as_str(), emb[0].clone()));
-            pb.inc(1);
+    // Each chunk is stored before the next is embedded so memory stays flat however many conditions there are
+    let mut skipped = Vec::new();
+    for (section, sections) in tables {
+        pb.set_message(format!("Embedding {} sections...", section));
+        let table_name = format!("{}_embeddings", section);
+        for chunk in sections.chunks(WRITE_CHUNK_SIZE) {
+            let data = embed_sections(model, chunk, section, model_choice.passage_prefix(), dimension, &pb, &mut skipped).await?;
+            create_and_insert_embeddings(&db, &table_name, data, dimension).await?;
         }
     }
     
     pb.finish_with_message("Embedding complete!");
     
-    let db = connect(DB_PATH).execute().await?;
-    
-    if !description_data.is_empty() {
-        println!("Storing {} description embeddings...", description_data.len());
-        create_and_insert_embeddings(&db, "description_embeddings", description_data).await?;
+    if !skipped.is_empty() {
+        // Without any stored rows a skipped condition counts as changed and is retried next run
+        let mut names: Vec<&str> = skipped.iter().map(|s| s.condition.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        delete_conditions(&db, &names).await?;
+        warn!("{} conditions skipped: {}", names.len(), describe_skipped(&skipped));
     }
     
-    if !etiology_data.is_empty() {
-        println!("Storing {} etiology embeddings...", etiology_data.len());
-        create_and_insert_embeddings(&db, "etiology_embeddings", etiology_data).await?;
+    build_indices(&db, false).await?;
+    write_version_marker(data_dir, model_choice, language)?;
+    
+    info!("All embeddings stored!");
+    Ok(conditions.iter().map(|c| c.name.clone()).collect())
+}
+
+/// Drop every embedding table and embed the conditions from scratch
+pub async fn rebuild_embeddings(
+    data_dir: &Path,
+    conditions: Vec<ConditionData>,
+    model: &EmbedderHandle,
+    model_choice: ModelChoice,
+    language: &str,
+) -> Result<Vec<String>> {
+    let db = open_db(data_dir).await?;
+    remove_version_marker(data_dir)?;
+    // A corrupt table may refuse to be dropped, in which case the whole database goes
+    if let Err(e) = drop_embedding_tables(&db).await {
+        warn!("Could not drop the embedding tables ({}); deleting {}", e, db_path(data_dir).display());
+        fs::remove_dir_all(db_path(data_dir))?;
+    }
+    embed_conditions(data_dir, conditions, model, model_choice, language).await
+}
+
+/// A condition section that couldn't be embedded, and why
+#[derive(Debug, Clone)]
+struct SkippedSection {
+    condition: String,
+    section: &'static str,
+    reason: String,
+}
+
+/// Most skipped sections listed in the summary after an embedding run
+const SKIPPED_LISTED: usize = 10;
+
+/// "Name (section: reason), ..." for the first few skipped sections
+fn describe_skipped(skipped: &[SkippedSection]) -> String {
+    let mut listed: Vec<String> = skipped
+        .iter()
+        .take(SKIPPED_LISTED)
+        .map(|s| format!("{} ({}: {})", s.condition, s.section, s.reason))
+        .collect();
+    if skipped.len() > SKIPPED_LISTED {
+        listed.push(format!("and {} more sections", skipped.len() - SKIPPED_LISTED));
+    }
+    listed.join(", ")
+}
+
+/// Embed a chunk of one table's sections in a single batched model call. If the batch fails,
+/// each section is retried alone and the ones that still fail are added to `skipped`.
+async fn embed_sections<'a>(
+    model: &EmbedderHandle,
+    sections: &[(&'a ConditionData, &'a str)],
+    section: &'static str,
+    passage_prefix: &str,
+    dimension: usize,
+    pb: &indicatif::ProgressBar,
+    skipped: &mut Vec<SkippedSection>,
+) -> Result<Vec<(&'a ConditionData, &'a str, Vec<f32>)>> {
+    if sections.is_empty() {
+        return Ok(Vec::new());
     }
     
-    if !manifestation_data.is_empty() {
-        println!("Storing {} manifestation embeddings...", manifestation_data.len());
-        create_and_insert_embeddings(&db, "manifestation_embeddings", manifestation_data).await?;
+    // Only the embedded text carries the prefix; the stored text stays as MedlinePlus wrote it
+    let texts: Vec<String> = sections.iter().map(|(_, text)| format!("{}{}", passage_prefix, text)).collect();
+    let embeddings: Vec<Result<Vec<f32>>> = match model.embed_batch(texts.clone()).await {
+        Ok(embeddings) => embeddings.into_iter().map(Ok).collect(),
+        Err(e) => {
+            warn!("Embedding {} {} sections together failed ({}); retrying them one at a time", texts.len(), section, e);
+            let mut embeddings = Vec::with_capacity(texts.len());
+            for text in texts {
+                embeddings.push(model.embed(text).await);
+            }
+            // A model that fails on everything is broken, not fed a bad input
+            if embeddings.iter().all(Result::is_err) {
+                return Err(e);
+            }
+            embeddings
+        }
+    };
+    
+    let mut data = Vec::with_capacity(sections.len());
+    for (&(condition, text), emb) in sections.iter().zip(embeddings) {
+        pb.inc(1);
+        let reason = match emb {
+            Ok(emb) if emb.len() == dimension => {
+                data.push((condition, text, emb));
+                continue;
+            }
+            Ok(emb) => format!("{} dimensions instead of {}", emb.len(), dimension),
+            Err(e) => e.to_string(),
+        };
+        skipped.push(SkippedSection {
+            condition: condition.name.clone(),
+            section,
+            reason,
+        });
     }
     
-    println!("All embeddings stored!");
-    Ok(())
+    Ok(data)
 }
 
-/// Create table and insert embeddings using simple flat vector storage
+/// Create table and insert embeddings as a native fixed-size vector column
 async fn create_and_insert_embeddings(
     db: &lancedb::Connection,
     table_name: &str,
-    data: Vec<(&str, &str, Vec<f32>)>,
+    data: Vec<(&ConditionData, &str, Vec<f32>)>,
+    embedding_dim: usize,
 ) -> Result<()> {
     if data.is_empty() {
         return Ok(());
     }
     
-    let embedding_dim = data[0].2.len();
-    println!("   Embedding dimension: {}", embedding_dim);
+    if let Some((condition, _, vec)) = data.iter().find(|(_, _, vec)| vec.len() != embedding_dim) {
+        bail!(
+            "Embedding for '{}' in {} has {} dimensions, expected {}",
+            condition.name,
+            table_name,
+            vec.len(),
+            embedding_dim
+        );
+    }
     
     let mut condition_names: Vec<String> = Vec::new();
     let mut texts: Vec<String> = Vec::new();
-    let mut vectors: Vec<String> = Vec::new();  // Store as JSON strings
+    let mut groups: Vec<String> = Vec::new();
+    let mut urls: Vec<String> = Vec::new();
+    let mut hashes: Vec<String> = Vec::new();
+    let mut sections: Vec<String> = Vec::new();
+    let created_at = Utc::now().timestamp_micros();
+    let mut flat_values: Vec<f32> = Vec::with_capacity(data.len() * embedding_dim);
     
-    for (name, text, vec) in data {
-        condition_names.push(name.to_string());
+    for (condition, text, mut vec) in data {
+        normalize(&mut vec);
+        condition_names.push(condition.name.clone());
         texts.push(text.to_string());
-        let vec_json = serde_json::to_string(&vec).unwrap_or_default();
-        vectors.push(vec_json);
+        groups.push(condition.groups.join(GROUP_SEPARATOR));
+        urls.push(condition.medline_url.clone());
+        hashes.push(content_hash(condition));
+        sections.push(SectionPresence::of(condition).to_column());
+        flat_values.extend_from_slice(&vec);
     }
     
+    let row_count = condition_names.len();
+    let item_field = Arc::new(arrow_schema::Field::new("item", arrow_schema::DataType::Float32, true));
+    let vectors = FixedSizeListArray::try_new(
+        item_field.clone(),
+        embedding_dim as i32,
+        Arc::new(Float32Array::from(flat_values)),
+        None,
+    )?;
+    
     let batch = RecordBatch::try_new(
         Arc::new(arrow_schema::Schema::new(vec![
             arrow_schema::Field::new("condition_name", arrow_schema::DataType::Utf8, false),
             arrow_schema::Field::new("text", arrow_schema::DataType::Utf8, false),
-            arrow_schema::Field::new("vector", arrow_schema::DataType::Utf8, false),
+            arrow_schema::Field::new("groups", arrow_schema::DataType::Utf8, false),
+            arrow_schema::Field::new("medline_url", arrow_schema::DataType::Utf8, false),
+            arrow_schema::Field::new("content_hash", arrow_schema::DataType::Utf8, false),
+            arrow_schema::Field::new("sections", arrow_schema::DataType::Utf8, false),
+            arrow_schema::Field::new("boilerplate", arrow_schema::DataType::Boolean, false),
+            arrow_schema::Field::new(
+                "created_at",
+                arrow_schema::DataType::Timestamp(arrow_schema::TimeUnit::Microsecond, Some("UTC".into())),
+                false,
+            ),
+            arrow_schema::Field::new(
+                "vector",
+                arrow_schema::DataType::FixedSizeList(item_field, embedding_dim as i32),
+                false,
+            ),
         ])),
         vec![
-            Arc::new(StringArray::from(condition_names)),
+            Arc::new(StringArray::from(condition_names.clone())),
             Arc::new(StringArray::from(texts)),
-            Arc::new(StringArray::from(vectors)),
+            Arc::new(StringArray::from(groups)),
+            Arc::new(StringArray::from(urls)),
+            Arc::new(StringArray::from(hashes)),
+            Arc::new(StringArray::from(sections)),
+            Arc::new(BooleanArray::from(vec![false; row_count])),
+            Arc::new(TimestampMicrosecondArray::from(vec![created_at; row_count]).with_timezone("UTC")),
+            Arc::new(vectors),
         ],
     )?;
     
     match db.open_table(table_name).execute().await {
         Ok(table) => {
+            let table_dim = vector_dimension(&table).await?;
+            if table_dim != Some(embedding_dim) {
+                bail!(
+                    "Table {} stores {} dimension vectors but the new embeddings have {}",
+                    table_name,
+                    table_dim.map_or("unknown".to_string(), |d| d.to_string()),
+                    embedding_dim
+                );
+            }
+            
+            // Replace rather than duplicate rows left by an earlier run
+            let names: Vec<&str> = condition_names.iter().map(String::as_str).collect();
+            delete_rows(&table, &names).await?;
+            
             let iter = RecordBatchIterator::new(std::iter::once(Ok::<_, arrow::error::ArrowError>(batch.clone())), batch.schema());
             table.add(iter).execute().await?;
         }
@@ -139,85 +818,1387 @@ async fn create_and_insert_embeddings(
     Ok(())
 }
 
-/// Search for similar embeddings - compute cosine similarity manually
+/// Rows a table search may return. LanceDB applies it before picking the nearest rows, so a
+/// narrow filter still fills the limit when matching rows exist.
+#[derive(Debug, Clone, Default)]
+pub struct RowFilter {
+    /// Skip rows embedded before this time
+    pub created_after: Option<DateTime<Utc>>,
+    /// Keep only these condition names, compared lowercased
+    pub names: Option<Vec<String>>,
+    /// Keep rows with a MedlinePlus group containing this text, ignoring case
+    pub group: Option<String>,
+}
+
+impl RowFilter {
+    /// SQL predicate for LanceDB's `only_if`, or `None` when every row may be returned
+    fn to_sql(&self) -> Option<String> {
+        let mut clauses = Vec::new();
+        if let Some(cutoff) = self.created_after {
+            clauses.push(format!("created_at >= TIMESTAMP '{}'", cutoff.format("%Y-%m-%d %H:%M:%S%.6f")));
+        }
+        if let Some(names) = &self.names {
+            let quoted: Vec<String> = names.iter().map(|n| format!("'{}'", n.to_lowercase().replace('\'', "''"))).collect();
+            // An empty list allows nothing, which `IN ()` can't express
+            clauses.push(if quoted.is_empty() {
+                "false".to_string()
+            } else {
+                format!("lower(condition_name) IN ({})", quoted.join(", "))
+            });
+        }
+        if let Some(group) = &self.group {
+            // LIKE's wildcards in the group name match only themselves
+            let pattern: String = group
+                .to_lowercase()
+                .chars()
+                .flat_map(|c| match c {
+                    '\\' | '%' | '_' => vec!['\\', c],
+                    '\'' => vec!['\'', '\''],
+                    c => vec![c],
+                })
+                .collect();
+            clauses.push(format!("lower(groups) LIKE '%{}%'", pattern));
+        }
+        (!clauses.is_empty()).then(|| clauses.join(" AND "))
+    }
+}
+
+/// Search for similar embeddings using LanceDB's nearest-neighbour search, among the rows `filter` allows.
+/// Results come nearest first with a score that is higher for closer matches under every metric.
+/// Rows that can't be read fail with [`CorruptTable`]; other errors, such as a bad filter, are returned as they are.
 pub async fn search_table(
     table: &lancedb::Table,
+    embedding_type: EmbeddingType,
     query_embedding: Vec<f32>,
     limit: usize,
+    filter: &RowFilter,
+    metric: Metric,
 ) -> Result<Vec<SearchResult>> {
-    let stream = table.query().execute().await?;
+    let start = Instant::now();
+    // The penalty only lowers scores, so once the fetch holds `limit` unflagged rows nothing
+    // left out can outrank them; until then fetch twice as many and try again
+    let mut fetch = limit;
+    let rows = loop {
+        let rows = nearest_rows(table, embedding_type, &query_embedding, fetch, filter, metric).await?;
+        let unflagged = rows.iter().filter(|(_, boilerplate)| !boilerplate).count();
+        if unflagged >= limit || rows.len() < fetch {
+            break rows;
+        }
+        fetch = fetch.saturating_mul(2);
+    };
+    let top_results = apply_boilerplate_penalty(rows, limit);
     
-    let results: Vec<RecordBatch> = stream.try_collect::<Vec<_>>().await?;
+    debug!(
+        "Searched {} embeddings in {:.1}ms ({} matches)",
+        embedding_type,
+        start.elapsed().as_secs_f64() * 1000.0,
+        top_results.len()
+    );
+    Ok(top_results)
+}
+
+/// The `limit` rows nearest the query, unpenalized, each with its boilerplate flag
+async fn nearest_rows(
+    table: &lancedb::Table,
+    embedding_type: EmbeddingType,
+    query_embedding: &[f32],
+    limit: usize,
+    filter: &RowFilter,
+    metric: Metric,
+) -> Result<Vec<(SearchResult, bool)>> {
+    let mut query = table
+        .query()
+        .nearest_to(query_embedding)?
+        .column("vector")
+        .distance_type(metric.distance_type())
+        .limit(limit);
+    // An index only answers queries by the distance it was built for
+    if metric != INDEX_METRIC {
+        query = query.bypass_vector_index();
+    }
+    if let Some(predicate) = filter.to_sql() {
+        query = query.only_if(predicate);
+    }
+    let stream = query.execute().await?;
+    
+    // A plan that builds but whose batches can't be read or decoded means damaged table files
+    let results: Vec<RecordBatch> = stream
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(|e| CorruptTable::new(table.name(), e))?;
     
-    let mut scored_results: Vec<(String, String, f32)> = Vec::new();
+    let mut rows = Vec::new();
     
     for batch in results.iter() {
         if let Some(name_col) = batch.column_by_name("condition_name") {
             if let Some(text_col) = batch.column_by_name("text") {
-                if let Some(vector_col) = batch.column_by_name("vector") {
+                if let Some(distance_col) = batch.column_by_name("_distance") {
                     let name_array = name_col.as_string::<i32>();
                     let text_array = text_col.as_string::<i32>();
-                    let vector_array = vector_col.as_string::<i32>();
+                    let distance_array = distance_col.as_primitive::<Float32Type>();
+                    let groups_array = batch.column_by_name("groups").map(|col| col.as_string::<i32>());
+                    let url_array = batch.column_by_name("medline_url").map(|col| col.as_string::<i32>());
+                    let sections_array = batch.column_by_name("sections").map(|col| col.as_string::<i32>());
+                    let boilerplate_array = batch.column_by_name("boilerplate").map(|col| col.as_boolean());
                     
                     for row_idx in 0..batch.num_rows() {
-                        let name = name_array.value(row_idx).to_string();
-                        let text = text_array.value(row_idx).to_string();
-                        let vector_str = vector_array.value(row_idx);
+                        let boilerplate = boilerplate_array.is_some_and(|array| array.value(row_idx));
+                        let groups = groups_array
+                            .map(|array| split_groups(array.value(row_idx)))
+                            .unwrap_or_default();
+                        let medline_url = url_array
+                            .map(|array| array.value(row_idx).to_string())
+                            .unwrap_or_default();
                         
-                        if let Ok(target_vec) = serde_json::from_str::<Vec<f32>>(vector_str) {
-                            let similarity = cosine_similarity(&query_embedding, &target_vec);
-                            scored_results.push((name, text, similarity));
-                        }
+                        let result = SearchResult {
+                            condition_name: name_array.value(row_idx).to_string(),
+                            text: text_array.value(row_idx).to_string(),
+                            embedding_type,
+                            sections: sections_array
+                                .map(|array| SectionPresence::from_column(array.value(row_idx)))
+                                .unwrap_or_default(),
+                            groups,
+                            medline_url,
+                            score: metric.score(distance_array.value(row_idx)),
+                        };
+                        rows.push((result, boilerplate));
                     }
                 }
             }
         }
     }
-    
-    scored_results.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal));
-    
-    let top_results: Vec<SearchResult> = scored_results
+    Ok(rows)
+}
+
+/// Lower the score of flagged rows, then keep the `limit` best in score order, nearest first on ties
+fn apply_boilerplate_penalty(rows: Vec<(SearchResult, bool)>, limit: usize) -> Vec<SearchResult> {
+    let mut results: Vec<SearchResult> = rows
         .into_iter()
-        .take(limit)
-        .map(|(name, text, _)| SearchResult {
-            condition_name: name,
-            text,
-            embedding_type: "".to_string(),
+        .map(|(mut result, boilerplate)| {
+            if boilerplate {
+                result.score -= BOILERPLATE_PENALTY;
+            }
+            result
         })
         .collect();
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+    results.truncate(limit);
+    results
+}
+
+/// Stored vector for a condition's section in this table, if the condition has one
+pub async fn get_embedding_for(table: &lancedb::Table, condition_name: &str) -> Result<Option<Vec<f32>>> {
+    let batches: Vec<RecordBatch> = table
+        .query()
+        .only_if(format!("condition_name = '{}'", condition_name.replace('\'', "''")))
+        .select(Select::columns(&["vector"]))
+        .limit(1)
+        .execute()
+        .await?
+        .try_collect()
+        .await?;
     
-    Ok(top_results)
+    let vector = batches
+        .iter()
+        .filter(|batch| batch.num_rows() > 0)
+        .find_map(|batch| batch.column_by_name("vector"))
+        .map(|col| {
+            let values = col.as_fixed_size_list().value(0);
+            values.as_primitive::<Float32Type>().values().to_vec()
+        });
+    Ok(vector)
 }
 
-/// Compute cosine similarity between two vectors
-fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+/// Every stored condition once, with the first section text found for it and a zero score
+pub async fn list_conditions(db: &lancedb::Connection) -> Result<Vec<SearchResult>> {
+    let mut seen = std::collections::HashSet::new();
+    let mut conditions = Vec::new();
+    
+    for name in db.table_names().execute().await? {
+        let Some(embedding_type) = EmbeddingType::from_table_name(&name) else {
+            continue;
+        };
+        
+        let table = db.open_table(&name).execute().await?;
+        let batches: Vec<RecordBatch> = table
+            .query()
+            .select(Select::columns(&["condition_name", "text", "groups", "medline_url", "sections"]))
+            .execute()
+            .await?
+            .try_collect()
+            .await?;
+        
+        for batch in &batches {
+            let (Some(name_col), Some(text_col)) = (batch.column_by_name("condition_name"), batch.column_by_name("text"))
+            else {
+                continue;
+            };
+            let name_array = name_col.as_string::<i32>();
+            let text_array = text_col.as_string::<i32>();
+            let groups_array = batch.column_by_name("groups").map(|col| col.as_string::<i32>());
+            let url_array = batch.column_by_name("medline_url").map(|col| col.as_string::<i32>());
+            let sections_array = batch.column_by_name("sections").map(|col| col.as_string::<i32>());
+            
+            for row_idx in 0..batch.num_rows() {
+                let condition_name = name_array.value(row_idx);
+                if !seen.insert(condition_name.to_string()) {
+                    continue;
+                }
+                
+                conditions.push(SearchResult {
+                    condition_name: condition_name.to_string(),
+                    text: text_array.value(row_idx).to_string(),
+                    embedding_type,
+                    sections: sections_array.map(|a| SectionPresence::from_column(a.value(row_idx))).unwrap_or_default(),
+                    groups: groups_array.map(|a| split_groups(a.value(row_idx))).unwrap_or_default(),
+                    medline_url: url_array.map(|a| a.value(row_idx).to_string()).unwrap_or_default(),
+                    score: 0.0,
+                });
+            }
+        }
+    }
+    
+    Ok(conditions)
+}
+
+/// Cosine similarity of two vectors, 0.0 when their lengths differ or either is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     if a.len() != b.len() {
         return 0.0;
     }
-    
-    let dot_product: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
-    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
-    
+    cosine_from(dot_and_norms(a, b))
+}
+
+/// `cosine_similarity` without the SIMD fast path, whichever features are enabled
+pub fn scalar_cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    cosine_from(scalar_dot_and_norms(a, b))
+}
+
+/// Cosine from a dot product and squared norms, 0.0 when either vector is all zeros
+fn cosine_from((dot_product, norm_a, norm_b): (f32, f32, f32)) -> f32 {
     if norm_a == 0.0 || norm_b == 0.0 {
         return 0.0;
     }
+    dot_product / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// Cosine similarity of two unit-length vectors, such as stored vectors and normalized queries
+pub fn unit_cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Reject a query vector that can't be compared with anything: one holding NaN or infinite
+/// values, or all zeros, would give every condition the same arbitrary score
+pub fn check_query_embedding(embedding: &[f32]) -> Result<()> {
+    if embedding.iter().any(|x| !x.is_finite()) {
+        bail!("The model returned an invalid embedding (NaN or infinite values) for this query; try rewording it");
+    }
+    if embedding.iter().all(|&x| x == 0.0) {
+        bail!("The model returned an empty (all-zero) embedding for this query; try rewording it");
+    }
+    Ok(())
+}
+
+/// Scale a vector to unit length in place, leaving an all-zero vector unchanged
+pub fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Dot product and squared norms of two equal-length vectors
+fn scalar_dot_and_norms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+    let mut dot = 0.0;
+    let mut norm_a = 0.0;
+    let mut norm_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    (dot, norm_a, norm_b)
+}
+
+#[cfg(not(feature = "simd"))]
+fn dot_and_norms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+    scalar_dot_and_norms(a, b)
+}
+
+/// Dot product and squared norms of two equal-length vectors, eight lanes at a time
+#[cfg(feature = "simd")]
+fn dot_and_norms(a: &[f32], b: &[f32]) -> (f32, f32, f32) {
+    use wide::f32x8;
+    
+    let mut dot = f32x8::ZERO;
+    let mut norm_a = f32x8::ZERO;
+    let mut norm_b = f32x8::ZERO;
+    let a_chunks = a.chunks_exact(8);
+    let b_chunks = b.chunks_exact(8);
+    let (a_rest, b_rest) = (a_chunks.remainder(), b_chunks.remainder());
+    
+    for (x, y) in a_chunks.zip(b_chunks) {
+        let x = f32x8::new(x.try_into().expect("chunk of 8"));
+        let y = f32x8::new(y.try_into().expect("chunk of 8"));
+        dot = x.mul_add(y, dot);
+        norm_a = x.mul_add(x, norm_a);
+        norm_b = y.mul_add(y, norm_b);
+    }
+    
+    // Lengths that aren't a multiple of 8 finish on the scalar path
+    let (rest_dot, rest_a, rest_b) = scalar_dot_and_norms(a_rest, b_rest);
+    (dot.reduce_add() + rest_dot, norm_a.reduce_add() + rest_a, norm_b.reduce_add() + rest_b)
+}
+
+/// Split a stored `groups` value back into group names
+fn split_groups(value: &str) -> Vec<String> {
+    value
+        .split(GROUP_SEPARATOR)
+        .filter(|g| !g.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Dimension of the `vector` column in an existing table
+async fn vector_dimension(table: &lancedb::Table) -> Result<Option<usize>> {
+    let schema = table.schema().await?;
+    Ok(match schema.field_with_name("vector").map(|f| f.data_type()) {
+        Ok(arrow_schema::DataType::FixedSizeList(_, dim)) => Some(*dim as usize),
+        _ => None,
+    })
+}
+
+/// Location of the LanceDB database inside the data directory
+pub fn db_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("lancedb")
+}
+
+/// Open the LanceDB database inside the data directory
+pub async fn open_db(data_dir: &Path) -> Result<lancedb::Connection> {
+    Ok(connect(&db_path(data_dir).to_string_lossy()).execute().await?)
+}
+
+/// Flag sections that nearly duplicate another condition's section in the same table, such as shared
+/// "when to see a doctor" paragraphs, so searches down-weight them. Only the `changed` conditions and
+/// the rows already flagged are checked, each against the whole table; `None` checks every row.
+/// Returns how many sections are flagged afterwards.
+pub async fn mark_boilerplate(db: &lancedb::Connection, changed: Option<&[String]>) -> Result<usize> {
+    let mut total = 0;
+    for section in SECTIONS {
+        let name = format!("{}_embeddings", section);
+        let Ok(table) = db.open_table(&name).execute().await else {
+            continue;
+        };
+        
+        let rows = stored_vectors(&table).await?;
+        let previously_flagged = flagged_names(&table).await?;
+        let recheck: Vec<bool> = match changed {
+            Some(changed) => rows
+                .iter()
+                .map(|(name, _)| changed.contains(name) || previously_flagged.contains(name))
+                .collect(),
+            None => vec![true; rows.len()],
+        };
+        let flagged = near_duplicates(&rows, &recheck);
+        
+        // Rows still duplicated are flagged again below, so a flag whose twin changed or went away doesn't linger
+        if !previously_flagged.is_empty() {
+            table
+                .update()
+                .only_if("boilerplate = true")
+                .column("boilerplate", "false")
+                .execute()
+                .await?;
+        }
+        for chunk in flagged.chunks(100) {
+            let quoted: Vec<String> = chunk.iter().map(|n| format!("'{}'", n.replace('\'', "''"))).collect();
+            table
+                .update()
+                .only_if(format!("condition_name IN ({})", quoted.join(", ")))
+                .column("boilerplate", "true")
+                .execute()
+                .await?;
+        }
+        
+        info!(
+            "{} of {} {} sections nearly duplicate another condition's ({} checked)",
+            flagged.len(),
+            rows.len(),
+            section,
+            recheck.iter().filter(|r| **r).count()
+        );
+        total += flagged.len();
+    }
+    Ok(total)
+}
+
+/// Names of the conditions whose section in this table is flagged as boilerplate
+async fn flagged_names(table: &lancedb::Table) -> Result<HashSet<String>> {
+    let batches: Vec<RecordBatch> = table
+        .query()
+        .only_if("boilerplate = true")
+        .select(Select::columns(&["condition_name"]))
+        .execute()
+        .await?
+        .try_collect()
+        .await?;
+    
+    let mut names = HashSet::new();
+    for batch in &batches {
+        if let Some(name_col) = batch.column_by_name("condition_name") {
+            let array = name_col.as_string::<i32>();
+            names.extend((0..batch.num_rows()).map(|row_idx| array.value(row_idx).to_string()));
+        }
+    }
+    Ok(names)
+}
+
+/// Every condition name in a table with its stored vector
+async fn stored_vectors(table: &lancedb::Table) -> Result<Vec<(String, Vec<f32>)>> {
+    let batches: Vec<RecordBatch> = table
+        .query()
+        .select(Select::columns(&["condition_name", "vector"]))
+        .execute()
+        .await?
+        .try_collect()
+        .await?;
+    
+    let mut rows = Vec::new();
+    for batch in &batches {
+        let (Some(name_col), Some(vector_col)) = (batch.column_by_name("condition_name"), batch.column_by_name("vector")) else {
+            continue;
+        };
+        let names = name_col.as_string::<i32>();
+        let vectors = vector_col.as_fixed_size_list();
+        for row_idx in 0..batch.num_rows() {
+            let vector = vectors.value(row_idx).as_primitive::<Float32Type>().values().to_vec();
+            rows.push((names.value(row_idx).to_string(), vector));
+        }
+    }
+    Ok(rows)
+}
+
+/// Names of the rows whose vector is at least `BOILERPLATE_SIMILARITY` to a different condition's.
+/// Only pairs with at least one row marked in `recheck` are compared, so a handful of changed rows
+/// costs one pass over the table rather than every pair.
+fn near_duplicates<'a>(rows: &'a [(String, Vec<f32>)], recheck: &[bool]) -> Vec<&'a str> {
+    let mut flagged = vec![false; rows.len()];
+    for (i, (name_a, a)) in rows.iter().enumerate().filter(|(i, _)| recheck[*i]) {
+        for (j, (name_b, b)) in rows.iter().enumerate() {
+            // Two rechecked rows are compared once, from the earlier one
+            if (recheck[j] && j <= i) || name_a == name_b {
+                continue;
+            }
+            if unit_cosine_similarity(a, b) >= BOILERPLATE_SIMILARITY {
+                flagged[i] = true;
+                flagged[j] = true;
+            }
+        }
+    }
+    rows.iter()
+        .zip(flagged)
+        .filter(|(_, flagged)| *flagged)
+        .map(|((name, _), _)| name.as_str())
+        .collect()
+}
+
+/// Build an IVF_PQ index on each embedding table's vectors, only for large tables unless forced
+pub async fn build_indices(db: &lancedb::Connection, force: bool) -> Result<()> {
+    for name in db.table_names().execute().await? {
+        if !name.ends_with("_embeddings") {
+            continue;
+        }
+        
+        let table = db.open_table(&name).execute().await?;
+        let rows = table.count_rows(None).await?;
+        if rows < MIN_INDEX_ROWS || (!force && rows < AUTO_INDEX_ROWS) {
+            if force {
+                debug!("Skipping index for {} ({} rows, need at least {})", name, rows, MIN_INDEX_ROWS);
+            }
+            continue;
+        }
+        
+        // Around sqrt(rows) partitions keeps each partition a reasonable size to scan
+        let partitions = ((rows as f64).sqrt() as u32).max(1);
+        let start = Instant::now();
+        table
+            .create_index(
+                &["vector"],
+                Index::IvfPq(
+                    IvfPqIndexBuilder::default()
+                        .distance_type(INDEX_METRIC.distance_type())
+                        .num_partitions(partitions),
+                ),
+            )
+            .replace(true)
+            .execute()
+            .await?;
+        info!(
+            "Indexed {} ({} rows, {} partitions) in {:.1}s",
+            name,
+            rows,
+            partitions,
+            start.elapsed().as_secs_f32()
+        );
+    }
+    Ok(())
+}
+
+/// Drop every embedding table in the database
+async fn drop_embedding_tables(db: &lancedb::Connection) -> Result<()> {
+    for name in db.table_names().execute().await? {
+        if name.ends_with("_embeddings") {
+            db.drop_table(&name, &[]).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Stable hash of everything stored for a condition, used to spot changed conditions
+fn content_hash(condition: &ConditionData) -> String {
+    stable_hash([
+        condition.name.as_str(),
+        condition.medline_url.as_str(),
+        &condition.groups.join(GROUP_SEPARATOR),
+        condition.description.as_str(),
+        condition.etiology.as_str(),
+        condition.manifestations.as_str(),
+        condition.treatments.as_str(),
+    ])
+}
+
+/// Content hash of every condition stored in any embedding table
+async fn stored_content_hashes(db: &lancedb::Connection) -> Result<HashMap<String, String>> {
+    let mut hashes = HashMap::new();
+    for name in db.table_names().execute().await? {
+        if !name.ends_with("_embeddings") {
+            continue;
+        }
+        
+        let table = db.open_table(&name).execute().await?;
+        let batches: Vec<RecordBatch> = table
+            .query()
+            .select(Select::columns(&["condition_name", "content_hash"]))
+            .execute()
+            .await?
+            .try_collect()
+            .await?;
+        
+        for batch in &batches {
+            let (Some(name_col), Some(hash_col)) =
+                (batch.column_by_name("condition_name"), batch.column_by_name("content_hash"))
+            else {
+                continue;
+            };
+            let names = name_col.as_string::<i32>();
+            let hash_values = hash_col.as_string::<i32>();
+            for row_idx in 0..batch.num_rows() {
+                hashes.insert(names.value(row_idx).to_string(), hash_values.value(row_idx).to_string());
+            }
+        }
+    }
+    Ok(hashes)
+}
+
+/// Delete every row for the given conditions from all embedding tables
+async fn delete_conditions(db: &lancedb::Connection, names: &[&str]) -> Result<()> {
+    for table_name in db.table_names().execute().await? {
+        if !table_name.ends_with("_embeddings") {
+            continue;
+        }
+        
+        let table = db.open_table(&table_name).execute().await?;
+        delete_rows(&table, names).await?;
+    }
+    Ok(())
+}
+
+/// Delete the rows for the given conditions from one table
+async fn delete_rows(table: &lancedb::Table, names: &[&str]) -> Result<()> {
+    for chunk in names.chunks(100) {
+        let quoted: Vec<String> = chunk.iter().map(|n| format!("'{}'", n.replace('\'', "''"))).collect();
+        table.delete(&format!("condition_name IN ({})", quoted.join(", "))).await?;
+    }
+    Ok(())
+}
+
+/// Record the current model and layout next to the tables
+fn write_version_marker(data_dir: &Path, model: ModelChoice, language: &str) -> Result<()> {
+    let marker = VersionMarker::current(model, language)?;
+    fs::create_dir_all(db_path(data_dir))?;
+    fs::write(db_path(data_dir).join(VERSION_FILE), serde_json::to_string_pretty(&marker)?)?;
+    Ok(())
+}
+
+/// Remove the marker so the tables are treated as incomplete until it is written again
+fn remove_version_marker(data_dir: &Path) -> Result<()> {
+    match fs::remove_file(db_path(data_dir).join(VERSION_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Read the marker describing how the tables were built, if there is one
+pub fn read_version_marker(data_dir: &Path) -> Option<VersionMarker> {
+    let content = fs::read_to_string(db_path(data_dir).join(VERSION_FILE)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Check that the tables on disk were built with the current model, language and layout
+pub fn check_version(data_dir: &Path, model: ModelChoice, language: &str) -> Result<()> {
+    let current = VersionMarker::current(model, language)?;
+    match read_version_marker(data_dir) {
+        Some(marker) if marker == current => Ok(()),
+        Some(marker) => bail!(
+            "Embedding database was built with {} ({} dims, storage v{}, lang {}{}) but this run uses {} ({} dims, storage v{}, lang {}). \
+             Re-run with the --model and --lang it was built with, or without --no-update to rebuild it.",
+            marker.model,
+            marker.dimension,
+            marker.storage_version,
+            marker.language,
+            if marker.normalized { "" } else { ", unnormalized vectors" },
+            current.model,
+            current.dimension,
+            current.storage_version,
+            current.language
+        ),
+        None => bail!(
+            "Embedding database at {} has no version marker. Re-run without --no-update to rebuild it.",
+            db_path(data_dir).display()
+        ),
+    }
+}
+
+/// Sections that each get their own `<section>_embeddings` table
+pub const SECTIONS: [&str; 4] = ["description", "etiology", "manifestation", "treatment"];
+
+/// Row count, vector size and embedding times of one embedding table
+#[derive(Debug, Clone, Serialize)]
+pub struct TableStats {
+    pub name: String,
+    pub exists: bool,
+    pub rows: usize,
+    pub dimension: Option<usize>,
+    pub oldest: Option<DateTime<Utc>>,
+    pub newest: Option<DateTime<Utc>>,
+}
+
+/// What the embedding database in a data directory holds
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseStats {
+    pub path: PathBuf,
+    pub marker: Option<VersionMarker>,
+    pub tables: Vec<TableStats>,
+    pub distinct_conditions: usize,
+    pub size_bytes: u64,
+}
+
+/// Count the rows, conditions and bytes stored in the database without loading a model
+pub async fn database_stats(data_dir: &Path) -> Result<DatabaseStats> {
+    let path = db_path(data_dir);
+    let db = open_db(data_dir).await?;
+    
+    let mut tables = Vec::new();
+    for section in SECTIONS {
+        let name = format!("{}_embeddings", section);
+        let stats = match db.open_table(&name).execute().await {
+            Ok(table) => {
+                let (oldest, newest) = created_at_range(&table).await?;
+                TableStats {
+                    rows: table.count_rows(None).await?,
+                    dimension: vector_dimension(&table).await?,
+                    exists: true,
+                    name,
+                    oldest,
+                    newest,
+                }
+            }
+            Err(_) => TableStats {
+                name,
+                exists: false,
+                rows: 0,
+                dimension: None,
+                oldest: None,
+                newest: None,
+            },
+        };
+        tables.push(stats);
+    }
+    
+    Ok(DatabaseStats {
+        marker: read_version_marker(data_dir),
+        tables,
+        distinct_conditions: list_conditions(&db).await?.len(),
+        size_bytes: dir_size(&path)?,
+        path,
+    })
+}
+
+/// Earliest and latest `created_at` in a table, `None` when it has no rows
+async fn created_at_range(table: &lancedb::Table) -> Result<(Option<DateTime<Utc>>, Option<DateTime<Utc>>)> {
+    let batches: Vec<RecordBatch> = table
+        .query()
+        .select(Select::columns(&["created_at"]))
+        .execute()
+        .await?
+        .try_collect()
+        .await?;
+    
+    let mut oldest: Option<i64> = None;
+    let mut newest: Option<i64> = None;
+    for batch in &batches {
+        let Some(col) = batch.column_by_name("created_at") else {
+            continue;
+        };
+        for &micros in col.as_primitive::<TimestampMicrosecondType>().values() {
+            oldest = Some(oldest.map_or(micros, |o| o.min(micros)));
+            newest = Some(newest.map_or(micros, |n| n.max(micros)));
+        }
+    }
+    Ok((oldest.and_then(DateTime::from_timestamp_micros), newest.and_then(DateTime::from_timestamp_micros)))
+}
+
+/// Total size of the files under a directory, 0 if it doesn't exist
+fn dir_size(path: &Path) -> Result<u64> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        total += if metadata.is_dir() { dir_size(&entry.path())? } else { metadata.len() };
+    }
+    Ok(total)
+}
+
+/// Check if compatible embeddings exist in at least one table, warning about any missing tables
+pub async fn has_embeddings(data_dir: &Path, model: ModelChoice, language: &str) -> bool {
+    if !db_path(data_dir).exists() {
+        return false;
+    }
     
-    dot_product / (norm_a * norm_b)
+    if let Err(e) = check_version(data_dir, model, language) {
+        warn!("{}", e);
+        return false;
+    }
+    
+    let Ok(db) = open_db(data_dir).await else {
+        return false;
+    };
+    let tables = existing_tables(&db).await;
+    if !tables.is_empty() && tables.len() < SECTIONS.len() {
+        warn!("Only these embedding tables exist: {}", tables.join(", "));
+    }
+    !tables.is_empty()
 }
 
-/// Check if embeddings exist in the database
-pub async fn has_embeddings() -> bool {
-    match connect(DB_PATH).execute().await {
-        Ok(db) => {
-            db.open_table("description_embeddings").execute().await.is_ok()
+/// Row count of the biggest section embedding table, 0 without any
+pub async fn largest_table_rows(db: &lancedb::Connection) -> Result<usize> {
+    let mut largest = 0;
+    for name in existing_tables(db).await {
+        largest = largest.max(get_table(db, &name).await?.count_rows(None).await?);
+    }
+    Ok(largest)
+}
+
+/// Names of the section embedding tables present in the database
+pub async fn existing_tables(db: &lancedb::Connection) -> Vec<String> {
+    let mut tables = Vec::new();
+    for section in SECTIONS {
+        let name = format!("{}_embeddings", section);
+        if db.open_table(&name).execute().await.is_ok() {
+            tables.push(name);
         }
-        Err(_) => false,
     }
+    tables
+}
+
+/// First section table that exists on disk but can't be opened or read back
+pub async fn find_corrupt_table(db: &lancedb::Connection) -> Option<CorruptTable> {
+    let stored = match db.table_names().execute().await {
+        Ok(names) => names,
+        Err(e) => return Some(CorruptTable::new("lancedb", e)),
+    };
+    
+    for section in SECTIONS {
+        let name = format!("{}_embeddings", section);
+        if !stored.contains(&name) {
+            continue;
+        }
+        if let Err(e) = read_first_row(db, &name).await {
+            return Some(CorruptTable::new(&name, e));
+        }
+    }
+    None
+}
+
+/// Open a table and read one row, which fails on a half-written table
+async fn read_first_row(db: &lancedb::Connection, table_name: &str) -> Result<()> {
+    let table = get_table(db, table_name).await?;
+    let _: Vec<RecordBatch> = table.query().limit(1).execute().await?.try_collect().await?;
+    Ok(())
 }
 
 /// Get a table from the database
-pub async fn get_table(table_name: &str) -> Result<lancedb::Table> {
-    let db = connect(DB_PATH).execute().await?;
+pub async fn get_table(db: &lancedb::Connection, table_name: &str) -> Result<lancedb::Table> {
     Ok(db.open_table(table_name).execute().await?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Condition with only a name, for tables filled by hand
+    fn condition(name: &str) -> ConditionData {
+        ConditionData {
+            name: name.to_string(),
+            medline_url: String::new(),
+            groups: Vec::new(),
+            description: String::new(),
+            etiology: String::new(),
+            manifestations: String::new(),
+            treatments: String::new(),
+        }
+    }
+    
+    /// Unit vector along one axis
+    fn axis(i: usize, dimension: usize) -> Vec<f32> {
+        let mut vector = vec![0.0; dimension];
+        vector[i] = 1.0;
+        vector
+    }
+    
+    /// Store one row per condition with the given vectors in `table_name`
+    async fn insert(db: &lancedb::Connection, table_name: &str, conditions: &[ConditionData], vectors: Vec<Vec<f32>>) -> Result<()> {
+        let dimension = vectors[0].len();
+        let data = conditions.iter().zip(vectors).map(|(c, v)| (c, "Section text long enough to keep", v)).collect();
+        create_and_insert_embeddings(db, table_name, data, dimension).await
+    }
+    
+    /// Embedder giving each text its length, noting which thread did the work
+    struct LengthEmbedder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+    
+    impl Embed for LengthEmbedder {
+        fn embed_texts(&mut self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+            let thread = std::thread::current().name().unwrap_or_default().to_string();
+            self.0.lock().unwrap().push(thread);
+            Ok(texts.iter().map(|t| vec![t.len() as f32]).collect())
+        }
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_embeds_share_one_model_thread() {
+        let threads = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handle = EmbedderHandle::spawn(LengthEmbedder(threads.clone())).unwrap();
+        
+        let tasks: Vec<_> = (0..16)
+            .map(|i| {
+                let handle = handle.clone();
+                tokio::spawn(async move { handle.embed("x".repeat(i)).await })
+            })
+            .collect();
+        for (i, task) in tasks.into_iter().enumerate() {
+            assert_eq!(task.await.unwrap().unwrap(), vec![i as f32]);
+        }
+        
+        let threads = threads.lock().unwrap();
+        assert_eq!(threads.len(), 16);
+        assert!(threads.iter().all(|t| t == "embedder"));
+    }
+    
+    #[test]
+    fn orthogonal_vectors_have_zero_similarity() {
+        assert_eq!(cosine_similarity(&axis(0, 768), &axis(1, 768)), 0.0);
+        assert_eq!(cosine_similarity(&[1.0, 2.0, 0.0], &[-2.0, 1.0, 5.0]), 0.0);
+    }
+    
+    #[test]
+    fn identical_vectors_have_similarity_one() {
+        let vector: Vec<f32> = (0..768).map(|i| (i as f32 * 0.37).sin()).collect();
+        assert!((cosine_similarity(&vector, &vector) - 1.0).abs() < 1e-5);
+        // Scale doesn't matter
+        let doubled: Vec<f32> = vector.iter().map(|x| x * 2.0).collect();
+        assert!((cosine_similarity(&vector, &doubled) - 1.0).abs() < 1e-5);
+    }
+    
+    #[test]
+    fn zero_length_vector_has_zero_similarity() {
+        assert_eq!(cosine_similarity(&[0.0; 16], &axis(3, 16)), 0.0);
+        assert_eq!(cosine_similarity(&[0.0; 16], &[0.0; 16]), 0.0);
+    }
+    
+    #[test]
+    fn empty_or_mismatched_vectors_have_zero_similarity() {
+        assert_eq!(cosine_similarity(&[], &[]), 0.0);
+        assert_eq!(cosine_similarity(&axis(0, 8), &axis(0, 9)), 0.0);
+    }
+    
+    #[test]
+    fn fast_path_matches_scalar_for_any_length() {
+        // Multiples of 8 and lengths with a remainder for the scalar tail
+        for len in [1, 7, 8, 13, 64, 384, 768, 1021] {
+            let a: Vec<f32> = (0..len).map(|i| (i as f32 * 0.11).cos()).collect();
+            let b: Vec<f32> = (0..len).map(|i| (i as f32 * 0.07 + 1.0).sin()).collect();
+            let (fast, scalar) = (cosine_similarity(&a, &b), scalar_cosine_similarity(&a, &b));
+            assert!((fast - scalar).abs() < 1e-5, "length {}: {} vs {}", len, fast, scalar);
+        }
+    }
+    
+    #[test]
+    fn degenerate_query_embeddings_are_rejected() {
+        assert!(check_query_embedding(&[0.0; 768]).unwrap_err().to_string().contains("all-zero"));
+        assert!(check_query_embedding(&[]).unwrap_err().to_string().contains("all-zero"));
+        assert!(check_query_embedding(&[0.3, f32::NAN]).unwrap_err().to_string().contains("NaN"));
+        assert!(check_query_embedding(&[f32::NEG_INFINITY, 1.0]).unwrap_err().to_string().contains("NaN or infinite"));
+        assert!(check_query_embedding(&[0.0, 1e-20, 0.0]).is_ok());
+    }
+    
+    #[test]
+    fn normalizing_keeps_the_cosine_ranking() {
+        // Vectors of very different lengths, so a plain dot product would rank them differently
+        let vectors: Vec<Vec<f32>> = (0..50)
+            .map(|i| (0..32).map(|j| ((i * 7 + j * 3) as f32 * 0.13).sin() * (1.0 + i as f32)).collect())
+            .collect();
+        let query: Vec<f32> = (0..32).map(|j| (j as f32 * 0.29).cos() * 5.0).collect();
+        let ranking = |score: &dyn Fn(&[f32]) -> f32| {
+            let mut order: Vec<usize> = (0..vectors.len()).collect();
+            order.sort_by(|&a, &b| score(&vectors[b]).total_cmp(&score(&vectors[a])));
+            order
+        };
+        
+        let mut unit_query = query.clone();
+        normalize(&mut unit_query);
+        let by_cosine = ranking(&|v| cosine_similarity(&query, v));
+        let by_unit_dot = ranking(&|v| {
+            let mut v = v.to_vec();
+            normalize(&mut v);
+            unit_cosine_similarity(&unit_query, &v)
+        });
+        assert_eq!(by_cosine, by_unit_dot);
+    }
+    
+    #[tokio::test]
+    async fn stored_vectors_rank_by_angle_not_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path()).await.unwrap();
+        let conditions = [condition("Long"), condition("Close")];
+        // "Long" points further from the query but is 100 times longer
+        insert(&db, "manifestation_embeddings", &conditions, vec![vec![100.0, 100.0, 0.0], vec![0.9, 0.1, 0.0]]).await.unwrap();
+        
+        let table = get_table(&db, "manifestation_embeddings").await.unwrap();
+        let stored = stored_vectors(&table).await.unwrap();
+        assert!(stored.iter().all(|(_, v)| (v.iter().map(|x| x * x).sum::<f32>() - 1.0).abs() < 1e-5));
+        let results = search_table(&table, EmbeddingType::Manifestation, vec![2.0, 0.0, 0.0], 2, &RowFilter::default(), Metric::Cosine).await.unwrap();
+        assert_eq!(results[0].condition_name, "Close");
+        assert!((results[0].score - cosine_similarity(&[2.0, 0.0, 0.0], &[0.9, 0.1, 0.0])).abs() < 1e-5);
+    }
+    
+    /// Unit vectors at 10, 40 and 80 degrees from the x axis, and a query along it of length 3
+    fn crafted_vectors() -> (Vec<Vec<f32>>, Vec<f32>) {
+        let at = |degrees: f32| vec![degrees.to_radians().cos(), degrees.to_radians().sin()];
+        (vec![at(80.0), at(10.0), at(40.0)], vec![3.0, 0.0])
+    }
+    
+    /// Distance LanceDB returns between two vectors under `metric`
+    fn lance_distance(metric: Metric, query: &[f32], stored: &[f32]) -> f32 {
+        let dot: f32 = query.iter().zip(stored).map(|(a, b)| a * b).sum();
+        match metric {
+            Metric::Cosine => 1.0 - cosine_similarity(query, stored),
+            Metric::Dot => 1.0 - dot,
+            Metric::Euclidean => query.iter().zip(stored).map(|(a, b)| (a - b).powi(2)).sum(),
+        }
+    }
+    
+    /// Scores `metric` gives `stored` vectors for a query prepared as cross_reference_search would
+    fn scores(metric: Metric, stored: &[Vec<f32>], mut query: Vec<f32>) -> Vec<f32> {
+        if metric.normalizes_query() {
+            normalize(&mut query);
+        }
+        stored.iter().map(|v| metric.score(lance_distance(metric, &query, v))).collect()
+    }
+    
+    /// Scores `metric` gives the crafted vectors
+    fn crafted_scores(metric: Metric) -> Vec<f32> {
+        let (stored, query) = crafted_vectors();
+        scores(metric, &stored, query)
+    }
+    
+    /// Vectors along or near the x axis at different lengths, and a unit query along it:
+    /// the short one points straight at the query, the long one is further off but much longer
+    fn unnormalized_vectors() -> (Vec<Vec<f32>>, Vec<f32>) {
+        (vec![vec![0.5, 0.0], vec![3.0, 1.0], vec![0.9, 0.5]], vec![1.0, 0.0])
+    }
+    
+    #[test]
+    fn metrics_rank_vectors_of_different_lengths_differently() {
+        let (stored, query) = unnormalized_vectors();
+        assert_eq!(order(&scores(Metric::Cosine, &stored, query.clone())), [0, 1, 2]);
+        assert_eq!(order(&scores(Metric::Dot, &stored, query.clone())), [1, 2, 0]);
+        assert_eq!(order(&scores(Metric::Euclidean, &stored, query)), [0, 2, 1]);
+    }
+    
+    /// Indexes of the crafted vectors, best score first
+    fn order(scores: &[f32]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..scores.len()).collect();
+        order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+        order
+    }
+    
+    #[test]
+    fn cosine_ranks_the_smallest_angle_first() {
+        let scores = crafted_scores(Metric::Cosine);
+        assert_eq!(order(&scores), [1, 2, 0]);
+        assert!((scores[1] - 10f32.to_radians().cos()).abs() < 1e-5);
+    }
+    
+    #[test]
+    fn dot_ranks_like_cosine_scaled_by_the_query_length() {
+        let (cosine, dot) = (crafted_scores(Metric::Cosine), crafted_scores(Metric::Dot));
+        assert_eq!(order(&dot), [1, 2, 0]);
+        for (c, d) in cosine.iter().zip(&dot) {
+            assert!((d - 3.0 * c).abs() < 1e-5);
+        }
+    }
+    
+    #[test]
+    fn euclidean_ranks_the_nearest_vector_first() {
+        let scores = crafted_scores(Metric::Euclidean);
+        let (stored, mut query) = crafted_vectors();
+        normalize(&mut query);
+        let distances: Vec<f32> = stored
+            .iter()
+            .map(|v| v.iter().zip(&query).map(|(a, b)| (a - b).powi(2)).sum::<f32>().sqrt())
+            .collect();
+        
+        // Nearest first is the reverse of the distances' order
+        let mut nearest: Vec<usize> = order(&distances);
+        nearest.reverse();
+        assert_eq!(order(&scores), nearest);
+        assert_eq!(order(&scores), [1, 2, 0]);
+        for (score, distance) in scores.iter().zip(&distances) {
+            assert!((score - (1.0 - distance / 2.0)).abs() < 1e-5);
+        }
+    }
+    
+    #[tokio::test]
+    async fn every_metric_searches_in_the_same_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path()).await.unwrap();
+        let (stored, query) = crafted_vectors();
+        let conditions = [condition("Far"), condition("Near"), condition("Middle")];
+        insert(&db, "etiology_embeddings", &conditions, stored).await.unwrap();
+        let table = get_table(&db, "etiology_embeddings").await.unwrap();
+        
+        for metric in [Metric::Cosine, Metric::Dot, Metric::Euclidean] {
+            // As cross_reference_search prepares it
+            let mut query = query.clone();
+            if metric.normalizes_query() {
+                normalize(&mut query);
+            }
+            let results = search_table(&table, EmbeddingType::Etiology, query, 3, &RowFilter::default(), metric).await.unwrap();
+            let names: Vec<&str> = results.iter().map(|r| r.condition_name.as_str()).collect();
+            assert_eq!(names, ["Near", "Middle", "Far"], "{:?}", metric);
+            let expected = crafted_scores(metric);
+            assert!((results[0].score - expected[1]).abs() < 1e-4, "{:?}", metric);
+        }
+    }
+    
+    #[tokio::test]
+    async fn each_metric_searches_with_its_own_distance() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path()).await.unwrap();
+        let (stored, query) = unnormalized_vectors();
+        
+        // Written directly, since create_and_insert_embeddings would scale the vectors to unit length
+        let item = Arc::new(arrow_schema::Field::new("item", arrow_schema::DataType::Float32, true));
+        let vectors = FixedSizeListArray::try_new(item.clone(), 2, Arc::new(Float32Array::from(stored.concat())), None).unwrap();
+        let schema = Arc::new(arrow_schema::Schema::new(vec![
+            arrow_schema::Field::new("condition_name", arrow_schema::DataType::Utf8, false),
+            arrow_schema::Field::new("text", arrow_schema::DataType::Utf8, false),
+            arrow_schema::Field::new("vector", arrow_schema::DataType::FixedSizeList(item, 2), false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["Short", "Long", "Middle"])),
+                Arc::new(StringArray::from(vec!["Section text"; 3])),
+                Arc::new(vectors),
+            ],
+        )
+        .unwrap();
+        let table = db
+            .create_table("description_embeddings", RecordBatchIterator::new([Ok(batch)], schema))
+            .execute()
+            .await
+            .unwrap();
+        
+        for (metric, expected) in [
+            (Metric::Cosine, ["Short", "Long", "Middle"]),
+            (Metric::Dot, ["Long", "Middle", "Short"]),
+            (Metric::Euclidean, ["Short", "Middle", "Long"]),
+        ] {
+            let results = search_table(&table, EmbeddingType::Description, query.clone(), 3, &RowFilter::default(), metric).await.unwrap();
+            let names: Vec<&str> = results.iter().map(|r| r.condition_name.as_str()).collect();
+            assert_eq!(names, expected, "{:?}", metric);
+        }
+    }
+    
+    #[tokio::test]
+    async fn nearest_of_100_vectors_comes_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path()).await.unwrap();
+        let conditions: Vec<ConditionData> = (0..100).map(|i| condition(&format!("Condition {}", i))).collect();
+        insert(&db, "description_embeddings", &conditions, (0..100).map(|i| axis(i, 100)).collect()).await.unwrap();
+        
+        // Mostly condition 42, a little of condition 7
+        let mut query = axis(42, 100);
+        query[7] = 0.3;
+        let table = get_table(&db, "description_embeddings").await.unwrap();
+        let results = search_table(&table, EmbeddingType::Description, query, 5, &RowFilter::default(), Metric::Cosine).await.unwrap();
+        
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].condition_name, "Condition 42");
+        assert_eq!(results[1].condition_name, "Condition 7");
+        assert!(results.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert!(results[0].score > 0.9);
+    }
+    
+    #[tokio::test]
+    async fn short_vector_is_rejected_with_a_clear_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path()).await.unwrap();
+        let conditions = [condition("Asthma"), condition("Gout")];
+        
+        let error = insert(&db, "etiology_embeddings", &conditions, vec![axis(0, 4), axis(0, 3)]).await.unwrap_err();
+        assert_eq!(error.to_string(), "Embedding for 'Gout' in etiology_embeddings has 3 dimensions, expected 4");
+        
+        // A table keeps the dimension it was created with
+        insert(&db, "etiology_embeddings", &conditions[..1], vec![axis(0, 4)]).await.unwrap();
+        let error = insert(&db, "etiology_embeddings", &conditions[1..], vec![axis(0, 3)]).await.unwrap_err();
+        assert_eq!(error.to_string(), "Table etiology_embeddings stores 4 dimension vectors but the new embeddings have 3");
+    }
+    
+    #[tokio::test]
+    async fn damaged_table_files_give_the_rebuild_hint() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path()).await.unwrap();
+        let conditions = [condition("Asthma"), condition("Gout")];
+        insert(&db, "manifestation_embeddings", &conditions, vec![axis(0, 8), axis(1, 8)]).await.unwrap();
+        
+        // Overwrite the stored rows, leaving the table's manifest in place
+        let data_dir = db_path(dir.path()).join("manifestation_embeddings.lance").join("data");
+        for file in fs::read_dir(&data_dir).unwrap() {
+            fs::write(file.unwrap().path(), b"not a lance data file").unwrap();
+        }
+        
+        let weights = crate::search::Weights::default();
+        let options = crate::search::SearchOptions {
+            top_k_per_table: 5,
+            num_results: 5,
+            min_score: 0.0,
+            group: None,
+            allowed: None,
+            weights: &weights,
+            metric: Metric::Cosine,
+            created_after: None,
+        };
+        let error = crate::search::cross_reference_search(&db, axis(0, 8), &options, &|_, _| {}).await.unwrap_err();
+        let corrupt = error.downcast_ref::<CorruptTable>().expect("a CorruptTable error");
+        assert_eq!(corrupt.table, "manifestation_embeddings");
+        assert!(error.to_string().contains("appears corrupt") && error.to_string().contains("--rebuild"), "{}", error);
+    }
+    
+    #[tokio::test]
+    async fn query_of_the_wrong_dimension_is_not_called_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path()).await.unwrap();
+        insert(&db, "description_embeddings", &[condition("Asthma")], vec![axis(0, 8)]).await.unwrap();
+        
+        let table = get_table(&db, "description_embeddings").await.unwrap();
+        let error = search_table(&table, EmbeddingType::Description, axis(0, 3), 5, &RowFilter::default(), Metric::Cosine).await.unwrap_err();
+        assert!(error.downcast_ref::<CorruptTable>().is_none(), "{}", error);
+    }
+    
+    #[tokio::test]
+    async fn embedding_twice_keeps_one_row_per_condition() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path()).await.unwrap();
+        let conditions = [condition("Asthma"), condition("Gout"), condition("Migraine")];
+        let vectors = || (0..3).map(|i| axis(i, 8)).collect();
+        
+        insert(&db, "treatment_embeddings", &conditions, vectors()).await.unwrap();
+        insert(&db, "treatment_embeddings", &conditions, vectors()).await.unwrap();
+        
+        let table = get_table(&db, "treatment_embeddings").await.unwrap();
+        assert_eq!(table.count_rows(None).await.unwrap(), 3);
+        let results = search_table(&table, EmbeddingType::Treatment, axis(1, 8), 10, &RowFilter::default(), Metric::Cosine).await.unwrap();
+        assert_eq!(results.iter().filter(|r| r.condition_name == "Gout").count(), 1);
+    }
+    
+    /// Search hit for `name` with `score`, flagged as boilerplate or not
+    fn hit(name: &str, score: f32, boilerplate: bool) -> (SearchResult, bool) {
+        let result = SearchResult {
+            condition_name: name.to_string(),
+            text: String::new(),
+            embedding_type: EmbeddingType::Description,
+            sections: SectionPresence::default(),
+            groups: Vec::new(),
+            medline_url: String::new(),
+            score,
+        };
+        (result, boilerplate)
+    }
+    
+    #[test]
+    fn penalized_rows_are_resorted_before_the_cut() {
+        // LanceDB's order: the flagged row is nearest but drops below both others once penalized
+        let rows = vec![hit("Shared", 0.9, true), hit("First", 0.8, false), hit("Second", 0.7, false)];
+        let results = apply_boilerplate_penalty(rows, 2);
+        let names: Vec<&str> = results.iter().map(|r| r.condition_name.as_str()).collect();
+        assert_eq!(names, ["First", "Second"]);
+    }
+    
+    #[test]
+    fn penalty_lowers_negative_scores_too() {
+        // Opposite-facing matches score below zero; the flagged one must not climb above its unflagged peer
+        let rows = vec![hit("Unflagged", -0.4, false), hit("Shared", -0.3, true)];
+        let results = apply_boilerplate_penalty(rows, 2);
+        assert_eq!(results[0].condition_name, "Unflagged");
+        assert!(results[1].score < -0.3);
+    }
+    
+    /// Rows A and B are near copies of each other, C is far from both
+    fn duplicate_rows() -> Vec<(String, Vec<f32>)> {
+        let mut nearly_x = vec![0.99, 0.1, 0.0];
+        normalize(&mut nearly_x);
+        vec![("A".to_string(), axis(0, 3)), ("B".to_string(), nearly_x), ("C".to_string(), axis(2, 3))]
+    }
+    
+    #[test]
+    fn checking_every_row_flags_both_copies() {
+        let rows = duplicate_rows();
+        assert_eq!(near_duplicates(&rows, &[true; 3]), ["A", "B"]);
+        assert!(near_duplicates(&rows, &[false; 3]).is_empty());
+    }
+    
+    #[test]
+    fn a_changed_row_flags_its_unchanged_twin() {
+        let rows = duplicate_rows();
+        assert_eq!(near_duplicates(&rows, &[false, true, false]), ["A", "B"]);
+        assert!(near_duplicates(&rows, &[false, false, true]).is_empty());
+    }
+    
+    #[tokio::test]
+    async fn flags_of_a_changed_twin_are_cleared() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path()).await.unwrap();
+        let conditions = [condition("A"), condition("B"), condition("C")];
+        let mut nearly_x = vec![0.99, 0.1, 0.0];
+        normalize(&mut nearly_x);
+        insert(&db, "description_embeddings", &conditions, vec![axis(0, 3), nearly_x, axis(2, 3)]).await.unwrap();
+        assert_eq!(mark_boilerplate(&db, None).await.unwrap(), 2);
+        
+        // B is re-embedded far from A, so A has no twin left even though it didn't change
+        delete_conditions(&db, &["B"]).await.unwrap();
+        insert(&db, "description_embeddings", &[condition("B")], vec![axis(1, 3)]).await.unwrap();
+        assert_eq!(mark_boilerplate(&db, Some(&["B".to_string()])).await.unwrap(), 0);
+        let table = get_table(&db, "description_embeddings").await.unwrap();
+        assert!(flagged_names(&table).await.unwrap().is_empty());
+    }
+    
+    #[test]
+    fn row_filter_quotes_names_and_joins_clauses() {
+        assert_eq!(RowFilter::default().to_sql(), None);
+        let filter = RowFilter {
+            created_after: Some(DateTime::from_timestamp(0, 0).unwrap()),
+            names: Some(vec!["Crohn's Disease".to_string(), "GOUT".to_string()]),
+            group: None,
+        };
+        assert_eq!(
+            filter.to_sql().unwrap(),
+            "created_at >= TIMESTAMP '1970-01-01 00:00:00.000000' AND lower(condition_name) IN ('crohn''s disease', 'gout')"
+        );
+        let nothing = RowFilter { names: Some(Vec::new()), ..RowFilter::default() };
+        assert_eq!(nothing.to_sql().unwrap(), "false");
+        let group = RowFilter { group: Some("Kids' 100%_Health".to_string()), ..RowFilter::default() };
+        assert_eq!(group.to_sql().unwrap(), r"lower(groups) LIKE '%kids'' 100\%\_health%'");
+    }
+    
+    #[tokio::test]
+    async fn narrow_group_keeps_its_matches_beyond_the_unfiltered_top_k() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path()).await.unwrap();
+        let conditions: Vec<ConditionData> = (0..10)
+            .map(|i| ConditionData {
+                groups: vec![if i == 8 { "Rare Diseases" } else { "Infections" }.to_string()],
+                ..condition(&format!("Condition {}", i))
+            })
+            .collect();
+        // The higher the number, the further the condition is from the query
+        let vectors = (0..10)
+            .map(|i| {
+                let mut vector = vec![1.0, i as f32];
+                normalize(&mut vector);
+                vector
+            })
+            .collect();
+        insert(&db, "description_embeddings", &conditions, vectors).await.unwrap();
+        
+        let weights = crate::search::Weights::default();
+        let options = crate::search::SearchOptions {
+            top_k_per_table: 3,
+            num_results: 5,
+            min_score: 0.0,
+            group: Some("rare"),
+            allowed: None,
+            weights: &weights,
+            metric: Metric::Cosine,
+            created_after: None,
+        };
+        let ranked = crate::search::cross_reference_search(&db, axis(0, 2), &options, &|_, _| {}).await.unwrap();
+        let names: Vec<&str> = ranked.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["Condition 8"]);
+    }
+    
+    #[tokio::test]
+    async fn allowed_condition_beyond_the_unfiltered_top_k_is_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path()).await.unwrap();
+        let conditions: Vec<ConditionData> = (0..10).map(|i| condition(&format!("Condition {}", i))).collect();
+        // The higher the number, the further the condition is from the query
+        let vectors = (0..10)
+            .map(|i| {
+                let mut vector = vec![1.0, i as f32];
+                normalize(&mut vector);
+                vector
+            })
+            .collect();
+        insert(&db, "description_embeddings", &conditions, vectors).await.unwrap();
+        let list = dir.path().join("allowed.txt");
+        fs::write(&list, "condition 7\n").unwrap();
+        let allowed = crate::search::AllowList::load(&list).unwrap();
+        
+        let weights = crate::search::Weights::default();
+        let options = crate::search::SearchOptions {
+            top_k_per_table: 3,
+            num_results: 5,
+            min_score: 0.0,
+            group: None,
+            allowed: Some(&allowed),
+            weights: &weights,
+            metric: Metric::Cosine,
+            created_after: None,
+        };
+        let ranked = crate::search::cross_reference_search(&db, axis(0, 2), &options, &|_, _| {}).await.unwrap();
+        let names: Vec<&str> = ranked.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["Condition 7"]);
+    }
+}
@@ -0,0 +1,548 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::embedding::{self, Embed, EmbedderHandle, EmbeddingType, ExecutionProvider, Metric, ModelChoice, RowFilter, SearchResult, VersionMarker};
+use crate::fetch::ConditionData;
+use crate::search::{self, AllowList, OnTable, RankedCondition, SearchOptions, SynonymMap, Weights};
+
+/// Fewest letters or digits a query needs before it is worth embedding
+const MIN_QUERY_CHARS: usize = 3;
+
+/// Number of query embeddings kept for repeated searches
+const QUERY_CACHE_SIZE: usize = 128;
+
+/// Least-recently-used cache of query embeddings keyed on the normalized query
+#[derive(Default)]
+pub struct QueryCache {
+    entries: HashMap<String, Vec<f32>>,
+    order: VecDeque<String>,
+}
+
+impl QueryCache {
+    /// Cache key for a query, ignoring surrounding whitespace and case
+    fn key(query: &str) -> String {
+        query.trim().to_lowercase()
+    }
+    
+    /// Look up a cached embedding and mark it as recently used
+    pub fn get(&mut self, query: &str) -> Option<Vec<f32>> {
+        let key = Self::key(query);
+        let embedding = self.entries.get(&key)?.clone();
+        self.order.retain(|k| *k != key);
+        self.order.push_back(key);
+        Some(embedding)
+    }
+    
+    /// Store an embedding, evicting the least recently used one when full
+    pub fn insert(&mut self, query: &str, embedding: Vec<f32>) {
+        let key = Self::key(query);
+        self.order.retain(|k| *k != key);
+        if self.order.len() >= QUERY_CACHE_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.push_back(key.clone());
+        self.entries.insert(key, embedding);
+    }
+    
+    /// Number of cached embeddings
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    
+    /// Whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Reject queries too short or too bare to describe any symptom
+pub fn validate_query(query: &str) -> Result<()> {
+    if query.chars().filter(|c| c.is_alphanumeric()).count() < MIN_QUERY_CHARS {
+        bail!(
+            "Please describe your symptoms in a few words (at least {} letters), e.g. \"headache and fever\"",
+            MIN_QUERY_CHARS
+        );
+    }
+    Ok(())
+}
+
+/// Stands in for the model on a read-only engine, which only searches precomputed embeddings
+struct NoModel;
+
+impl Embed for NoModel {
+    fn embed_texts(&mut self, _texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        bail!("No embedding model is loaded; only precomputed query embeddings can be searched")
+    }
+}
+
+/// Loaded embedding model and database connection, reused across searches
+pub struct SearchEngine {
+    model: EmbedderHandle,
+    db: lancedb::Connection,
+    read_only: bool,
+    data_dir: PathBuf,
+    model_choice: ModelChoice,
+    language: String,
+    query_cache: Mutex<QueryCache>,
+    top_k: usize,
+    min_score: f32,
+    group: Option<String>,
+    allowed: Option<AllowList>,
+    metric: Metric,
+    preprocess_query: bool,
+    synonyms: Option<SynonymMap>,
+    aliases: Option<SynonymMap>,
+    bundled_aliases: SynonymMap,
+    dedup_sections: bool,
+    weights: Weights,
+    created_after: Option<DateTime<Utc>>,
+}
+
+impl SearchEngine {
+    /// Load the chosen embedding model on the given backend and open the database in `data_dir`
+    pub async fn open(
+        data_dir: impl Into<PathBuf>,
+        model_choice: ModelChoice,
+        provider: ExecutionProvider,
+    ) -> Result<Self> {
+        let data_dir = data_dir.into();
+        let model = EmbedderHandle::spawn(embedding::load_model(model_choice, provider, &data_dir)?)?;
+        Self::with_embedder(data_dir, model_choice, model).await
+    }
+    
+    /// Open the database in `data_dir` to search precomputed query embeddings, without loading a
+    /// model. The engine refuses to embed, rebuild or index anything.
+    pub async fn open_read_only(data_dir: impl Into<PathBuf>, model_choice: ModelChoice) -> Result<Self> {
+        let mut engine = Self::with_embedder(data_dir, model_choice, EmbedderHandle::spawn(NoModel)?).await?;
+        engine.read_only = true;
+        Ok(engine)
+    }
+    
+    /// Open the database in `data_dir` around an embedder that is already running
+    pub async fn with_embedder(data_dir: impl Into<PathBuf>, model_choice: ModelChoice, model: EmbedderHandle) -> Result<Self> {
+        let data_dir = data_dir.into();
+        let db = embedding::open_db(&data_dir).await?;
+        
+        Ok(Self {
+            model,
+            db,
+            read_only: false,
+            data_dir,
+            model_choice,
+            language: "en".to_string(),
+            query_cache: Mutex::new(QueryCache::default()),
+            top_k: 10,
+            min_score: 0.0,
+            group: None,
+            allowed: None,
+            metric: Metric::default(),
+            preprocess_query: false,
+            synonyms: None,
+            aliases: None,
+            bundled_aliases: SynonymMap::aliases(),
+            dedup_sections: false,
+            weights: Weights::default(),
+            created_after: None,
+        })
+    }
+    
+    /// Set the MedlinePlus language code the database is built from
+    pub fn with_language(mut self, language: impl Into<String>) -> Self {
+        self.language = language.into();
+        self
+    }
+    
+    /// Set how many candidates are pulled from each embedding table
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+    
+    /// Set the combined score below which conditions are dropped
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = min_score;
+        self
+    }
+    
+    /// Only return conditions in a MedlinePlus group whose name contains this text
+    pub fn with_group(mut self, group: Option<String>) -> Self {
+        self.group = group;
+        self
+    }
+    
+    /// Strip filler and normalize symptom wording from queries before embedding them
+    pub fn with_preprocess_query(mut self, enabled: bool) -> Self {
+        self.preprocess_query = enabled;
+        self
+    }
+    
+    /// Rewrite everyday symptom phrases in queries with this map before embedding them
+    pub fn with_synonyms(mut self, synonyms: Option<SynonymMap>) -> Self {
+        self.synonyms = synonyms;
+        self
+    }
+    
+    /// Flag sections that nearly duplicate another condition's after embedding, so searches down-weight them
+    pub fn with_dedup_sections(mut self, enabled: bool) -> Self {
+        self.dedup_sections = enabled;
+        self
+    }
+    
+    /// Replace the bundled abbreviation aliases with this map, used on every query whatever the language
+    pub fn with_aliases(mut self, aliases: Option<SynonymMap>) -> Self {
+        self.aliases = aliases;
+        self
+    }
+    
+    /// Abbreviations in a query and the condition names they are searched as
+    pub fn resolved_aliases<'a>(&'a self, query: &str) -> Vec<(&'a str, &'a str)> {
+        self.alias_map().map(|aliases| aliases.matches(query)).unwrap_or_default()
+    }
+    
+    /// Aliases applied to queries: the caller's map, or the bundled one, which names English
+    /// conditions and so would only skew the embedding of a query in another language
+    fn alias_map(&self) -> Option<&SynonymMap> {
+        match &self.aliases {
+            Some(aliases) => Some(aliases),
+            None if self.language == "en" => Some(&self.bundled_aliases),
+            None => None,
+        }
+    }
+    
+    /// Set the per-table scoring weights
+    pub fn with_weights(mut self, weights: Weights) -> Self {
+        self.weights = weights;
+        self
+    }
+    
+    /// Set how query vectors are compared with stored ones
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+    
+    /// Only return conditions on this list
+    pub fn with_allowed_conditions(mut self, allowed: Option<AllowList>) -> Self {
+        self.allowed = allowed;
+        self
+    }
+    
+    /// Ignore embeddings created before this time
+    pub fn with_created_after(mut self, created_after: Option<DateTime<Utc>>) -> Self {
+        self.created_after = created_after;
+        self
+    }
+    
+    /// Scoring weights used for searches
+    pub fn weights(&self) -> &Weights {
+        &self.weights
+    }
+    
+    /// Candidate count, filters and weights this engine searches with
+    pub fn search_options(&self, num_results: usize) -> SearchOptions<'_> {
+        SearchOptions {
+            top_k_per_table: self.top_k,
+            num_results,
+            min_score: self.min_score,
+            group: self.group.as_deref(),
+            allowed: self.allowed.as_ref(),
+            metric: self.metric,
+            weights: &self.weights,
+            created_after: self.created_after,
+        }
+    }
+    
+    /// Directory holding the MedlinePlus cache and embedding database
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+    
+    /// Embedding model the engine was opened with
+    pub fn model_choice(&self) -> ModelChoice {
+        self.model_choice
+    }
+    
+    /// Open database connection
+    pub fn db(&self) -> &lancedb::Connection {
+        &self.db
+    }
+    
+    /// Check if compatible embeddings exist in the database
+    pub async fn has_embeddings(&self) -> bool {
+        embedding::has_embeddings(&self.data_dir, self.model_choice, &self.language).await
+    }
+    
+    /// Embed and store conditions with the loaded model
+    pub async fn embed_conditions(&self, conditions: Vec<ConditionData>) -> Result<()> {
+        self.check_writable()?;
+        let changed = embedding::embed_conditions(&self.data_dir, conditions, &self.model, self.model_choice, &self.language).await?;
+        self.mark_boilerplate(Some(&changed)).await
+    }
+    
+    /// Drop the stored embeddings and embed the conditions from scratch
+    pub async fn rebuild(&self, conditions: Vec<ConditionData>) -> Result<()> {
+        self.check_writable()?;
+        embedding::rebuild_embeddings(&self.data_dir, conditions, &self.model, self.model_choice, &self.language).await?;
+        self.mark_boilerplate(None).await
+    }
+    
+    /// Flag near-duplicate sections when --dedup-sections asked for it, checking only the `changed`
+    /// conditions unless every row was just embedded
+    async fn mark_boilerplate(&self, changed: Option<&[String]>) -> Result<()> {
+        if self.dedup_sections {
+            let flagged = embedding::mark_boilerplate(&self.db, changed).await?;
+            info!("Flagged {} near-duplicate sections as boilerplate", flagged);
+        }
+        Ok(())
+    }
+    
+    /// Build vector indexes on every embedding table that is large enough
+    pub async fn build_index(&self) -> Result<()> {
+        self.check_writable()?;
+        embedding::build_indices(&self.db, true).await
+    }
+    
+    /// Fail on an engine opened with [`Self::open_read_only`]
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            bail!("The database was opened read-only");
+        }
+        Ok(())
+    }
+    
+    /// Embed a symptom description with the loaded model, reusing earlier embeddings of the same query
+    pub async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        if let Some(embedding) = self.query_cache().get(query) {
+            return Ok(embedding);
+        }
+        
+        let prefixed = format!("{}{}", self.model_choice.query_prefix(), query);
+        let embedding = self.model.embed(prefixed).await?;
+        embedding::check_query_embedding(&embedding)?;
+        self.query_cache().insert(query, embedding.clone());
+        Ok(embedding)
+    }
+    
+    /// Cache of embeddings for recent queries
+    pub fn query_cache(&self) -> MutexGuard<'_, QueryCache> {
+        self.query_cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    
+    /// Search for the conditions that best match a symptom description
+    pub async fn search(&self, query: &str, num_results: usize) -> Result<Vec<RankedCondition>> {
+        self.search_with_progress(query, num_results, &|_, _| {}).await
+    }
+    
+    /// Search like [`Self::search`], handing each table's matches to `on_table` as soon as they arrive
+    pub async fn search_with_progress(
+        &self,
+        query: &str,
+        num_results: usize,
+        on_table: OnTable<'_>,
+    ) -> Result<Vec<RankedCondition>> {
+        validate_query(query)?;
+        let query_embedding = self.embed_query(&self.query_text(query)).await?;
+        self.search_query_embedding(query, query_embedding, num_results, on_table).await
+    }
+    
+    /// Search for each of many queries, embedding them together in one batch
+    pub async fn search_batch(&self, queries: &[String], num_results: usize) -> Vec<Result<Vec<RankedCondition>>> {
+        let texts: Vec<String> = queries
+            .iter()
+            .filter(|q| validate_query(q).is_ok())
+            .map(|q| format!("{}{}", self.model_choice.query_prefix(), self.query_text(q)))
+            .collect();
+        let mut embeddings = match self.model.embed_batch(texts).await {
+            Ok(embeddings) => embeddings.into_iter(),
+            Err(e) => return queries.iter().map(|_| Err(anyhow!("Could not embed the queries: {}", e))).collect(),
+        };
+        
+        let mut results = Vec::with_capacity(queries.len());
+        for query in queries {
+            if let Err(e) = validate_query(query) {
+                results.push(Err(e));
+                continue;
+            }
+            let query_embedding = embeddings.next().expect("one embedding per valid query");
+            results.push(self.search_query_embedding(query, query_embedding, num_results, &|_, _| {}).await);
+        }
+        results
+    }
+    
+    /// Raw top-k matches for a query from one section's table, without cross-referencing
+    pub async fn search_single_table(&self, section: EmbeddingType, query: &str) -> Result<Vec<SearchResult>> {
+        validate_query(query)?;
+        let mut query_embedding = self.embed_query(&self.query_text(query)).await?;
+        if self.metric.normalizes_query() {
+            embedding::normalize(&mut query_embedding);
+        }
+        let table = embedding::get_table(&self.db, &section.table_name()).await?;
+        let filter = RowFilter { created_after: self.created_after, ..RowFilter::default() };
+        embedding::search_table(&table, section, query_embedding, self.top_k, &filter, self.metric).await
+    }
+    
+    /// Text that is embedded for a query; the caller keeps the original query
+    fn query_text(&self, query: &str) -> String {
+        // Queries without an abbreviation are embedded as typed
+        let expanded;
+        let query = match self.alias_map() {
+            Some(aliases) if !aliases.matches(query).is_empty() => {
+                expanded = aliases.expand(query);
+                &expanded
+            }
+            _ => query,
+        };
+        match (self.preprocess_query, &self.synonyms) {
+            (true, Some(synonyms)) => search::preprocess_query(query, synonyms),
+            (true, None) => search::preprocess_query(query, &SynonymMap::default()),
+            (false, Some(synonyms)) => synonyms.expand(query),
+            (false, None) => query.to_string(),
+        }
+    }
+    
+    /// Search with a query's embedding, falling back to condition names that resemble the query
+    async fn search_query_embedding(
+        &self,
+        query: &str,
+        query_embedding: Vec<f32>,
+        num_results: usize,
+        on_table: OnTable<'_>,
+    ) -> Result<Vec<RankedCondition>> {
+        let results = self.search_embedding_with_progress(query_embedding, num_results, on_table).await?;
+        if !results.is_empty() {
+            return Ok(results);
+        }
+        
+        // Condition names typed directly ("lupus") may not embed close to any section
+        search::name_match_search(&self.db, query, &self.search_options(num_results)).await
+    }
+    
+    /// Search for conditions similar to a stored condition, using its description's vector
+    /// (or another section's when it has no description), leaving the condition itself out
+    pub async fn search_similar(&self, condition_name: &str, num_results: usize) -> Result<Vec<RankedCondition>> {
+        let mut stored = None;
+        for table_name in embedding::existing_tables(&self.db).await {
+            let table = embedding::get_table(&self.db, &table_name).await?;
+            if let Some(vector) = embedding::get_embedding_for(&table, condition_name).await? {
+                stored = Some(vector);
+                break;
+            }
+        }
+        let Some(condition_embedding) = stored else {
+            bail!("No stored embedding found for '{}'", condition_name);
+        };
+        
+        let mut results = self.search_embedding(condition_embedding, num_results + 1).await?;
+        results.retain(|c| c.name != condition_name);
+        results.truncate(num_results);
+        Ok(results)
+    }
+    
+    /// Search with a precomputed query embedding instead of text
+    pub async fn search_embedding(&self, query_embedding: Vec<f32>, num_results: usize) -> Result<Vec<RankedCondition>> {
+        self.search_embedding_with_progress(query_embedding, num_results, &|_, _| {}).await
+    }
+    
+    /// Search with a precomputed embedding, handing each table's matches to `on_table` as soon as they arrive
+    async fn search_embedding_with_progress(
+        &self,
+        query_embedding: Vec<f32>,
+        num_results: usize,
+        on_table: OnTable<'_>,
+    ) -> Result<Vec<RankedCondition>> {
+        embedding::check_version(&self.data_dir, self.model_choice, &self.language)?;
+        let dimension = VersionMarker::current(self.model_choice, &self.language)?.dimension;
+        if query_embedding.len() != dimension {
+            bail!(
+                "Query embedding has {} values but the database expects {} dimensions",
+                query_embedding.len(),
+                dimension
+            );
+        }
+        
+        let options = self.search_options(num_results);
+        search::cross_reference_search(&self.db, query_embedding, &options, on_table).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    
+    /// Embedder that counts the batches it is asked for and returns a fixed unit vector
+    struct CountingEmbedder(Arc<AtomicUsize>);
+    
+    impl embedding::Embed for CountingEmbedder {
+        fn embed_texts(&mut self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(texts.iter().map(|_| vec![0.6, 0.8]).collect())
+        }
+    }
+    
+    #[tokio::test]
+    async fn repeated_query_is_served_from_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let model = EmbedderHandle::spawn(CountingEmbedder(calls.clone())).unwrap();
+        let engine = SearchEngine::with_embedder(dir.path(), ModelChoice::default(), model).await.unwrap();
+        
+        let first = engine.embed_query("Headache and fever").await.unwrap();
+        let second = engine.embed_query("  headache AND fever ").await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(engine.query_cache().len(), 1);
+    }
+    
+    #[test]
+    fn query_cache_evicts_the_least_recently_used_entry() {
+        let mut cache = QueryCache::default();
+        for i in 0..QUERY_CACHE_SIZE {
+            cache.insert(&format!("query {}", i), vec![i as f32]);
+        }
+        // Reading the oldest entry makes "query 1" the least recently used instead
+        assert_eq!(cache.get("QUERY 0"), Some(vec![0.0]));
+        cache.insert("one more", vec![-1.0]);
+        
+        assert_eq!(cache.len(), QUERY_CACHE_SIZE);
+        assert!(cache.get("query 0").is_some());
+        assert!(cache.get("query 1").is_none());
+        assert_eq!(cache.get("one more"), Some(vec![-1.0]));
+    }
+    
+    #[tokio::test]
+    async fn read_only_engine_neither_embeds_nor_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = SearchEngine::open_read_only(dir.path(), ModelChoice::default()).await.unwrap();
+        
+        assert!(engine.embed_query("headache and fever").await.is_err());
+        let error = engine.embed_conditions(Vec::new()).await.unwrap_err();
+        assert_eq!(error.to_string(), "The database was opened read-only");
+        assert!(engine.rebuild(Vec::new()).await.is_err());
+        assert!(engine.build_index().await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn bundled_aliases_only_expand_english_queries() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = SearchEngine::open_read_only(dir.path(), ModelChoice::default()).await.unwrap();
+        assert_eq!(engine.query_text("UTI"), "urinary tract infection");
+        
+        let engine = engine.with_language("es");
+        assert!(engine.resolved_aliases("UTI").is_empty());
+        assert_eq!(engine.query_text("UTI"), "UTI");
+        
+        // A map the user chose applies in any language
+        let aliases = dir.path().join("aliases.txt");
+        std::fs::write(&aliases, "itu = infección del tracto urinario\n").unwrap();
+        let engine = engine.with_aliases(SynonymMap::load(&aliases));
+        assert_eq!(engine.query_text("ITU"), "infección del tracto urinario");
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{bail, Result};
+use arrow_array::{RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::fetch::ConditionData;
+
+/// Separator between group names in exported files
+const EXPORT_GROUP_SEPARATOR: &str = ";";
+
+/// Column names shared by the CSV and Parquet exports
+const COLUMNS: [&str; 7] = [
+    "name",
+    "medline_url",
+    "groups",
+    "description",
+    "etiology",
+    "manifestations",
+    "treatments",
+];
+
+/// Write the conditions to `path` as CSV or Parquet, chosen by the file extension
+pub fn export_conditions(conditions: &[ConditionData], path: &Path) -> Result<()> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+    
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    
+    match extension.as_deref() {
+        Some("csv") => write_csv(conditions, path),
+        Some("parquet") => write_parquet(conditions, path),
+        _ => bail!("Cannot export to {}; use a .csv or .parquet file name", path.display()),
+    }
+}
+
+/// Values of every column for one condition, in `COLUMNS` order
+fn row(condition: &ConditionData) -> [String; 7] {
+    [
+        condition.name.clone(),
+        condition.medline_url.clone(),
+        condition.groups.join(EXPORT_GROUP_SEPARATOR),
+        condition.description.clone(),
+        condition.etiology.clone(),
+        condition.manifestations.clone(),
+        condition.treatments.clone(),
+    ]
+}
+
+/// Write one header line and one line per condition
+fn write_csv(conditions: &[ConditionData], path: &Path) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "{}", COLUMNS.join(","))?;
+    for condition in conditions {
+        let fields: Vec<String> = row(condition).iter().map(|f| csv_field(f)).collect();
+        writeln!(file, "{}", fields.join(","))?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Write all conditions as a single Parquet row group of string columns
+fn write_parquet(conditions: &[ConditionData], path: &Path) -> Result<()> {
+    let rows: Vec<[String; 7]> = conditions.iter().map(row).collect();
+    let schema = Arc::new(Schema::new(
+        COLUMNS
+            .iter()
+            .map(|name| Field::new(*name, DataType::Utf8, false))
+            .collect::<Vec<_>>(),
+    ));
+    let columns = (0..COLUMNS.len())
+        .map(|i| Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r[i]))) as _)
+        .collect();
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+    
+    let mut writer = parquet::arrow::ArrowWriter::try_new(File::create(path)?, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Quote a CSV field if it contains a delimiter, quote, or newline
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
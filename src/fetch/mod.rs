@@ -1,11 +1,176 @@
 use chrono::{Duration, Local};
-use roxmltree::{Document, ParsingOptions};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
 use serde::{Deserialize, Serialize};
-use std::error::Error;
+use std::collections::HashMap;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, info, warn};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tokio::task;
 
+/// Errors from downloading, parsing, or loading MedlinePlus data
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    #[error("Network request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Could not parse MedlinePlus data: {0}")]
+    Parse(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("No MedlinePlus XML dump found for the last 7 days")]
+    NoRecentDump,
+    #[error("No cached data found")]
+    NoCache,
+    #[error("Unsupported language '{0}'; use 'en' or 'es'")]
+    UnsupportedLanguage(String),
+    #[error("{0} returned a web page instead of MedlinePlus XML; the dump may have moved or been removed")]
+    NotXml(String),
+    #[error("MedlinePlus did not respond for {0}s")]
+    Timeout(u64),
+}
+
+impl From<quick_xml::Error> for FetchError {
+    fn from(e: quick_xml::Error) -> Self {
+        FetchError::Parse(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for FetchError {
+    fn from(e: serde_json::Error) -> Self {
+        FetchError::Parse(e.to_string())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for FetchError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        FetchError::Parse(e.to_string())
+    }
+}
+
+/// Cached copy of the latest MedlinePlus XML dump in the data directory
+const XML_FILE: &str = "mplus_topics_latest.xml";
+
+/// Where MedlinePlus publishes its dated XML dumps
+const DUMP_BASE_URL: &str = "https://medlineplus.gov/xml";
+
+/// Longest wait for a response to each request before it is retried
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Longest wait for the next part of the dump before the download is abandoned
+const STALL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Handful of synthetic English topics used by --demo in place of the MedlinePlus dump
+const DEMO_XML: &str = include_str!("../../tests/fixtures/demo_topics.xml");
+
+/// Which MedlinePlus topics are kept as conditions, read from the `[topics]` table of the config file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TopicFilter {
+    /// Title words that always keep a topic
+    pub include_keywords: Vec<String>,
+    /// Title words that drop a topic that matched no include keyword
+    pub exclude_keywords: Vec<String>,
+    /// Group names that keep a topic
+    pub group_keywords: Vec<String>,
+    /// Drop topics that match neither an include nor a group keyword instead of keeping them
+    pub strict: bool,
+    /// Warn when fewer conditions than this are kept, which usually means the filter or the dump changed
+    pub min_conditions: usize,
+}
+
+impl Default for TopicFilter {
+    fn default() -> Self {
+        let words = |list: &[&str]| list.iter().map(|w| w.to_string()).collect();
+        Self {
+            include_keywords: words(&[
+                "disease", "disorder", "syndrome", "cancer", "infection", "tumor", "arthritis", "diabetes",
+                "enfermedad", "trastorno", "síndrome", "cáncer", "infección",
+            ]),
+            exclude_keywords: words(&[
+                "healthy", "nutrition", "exercise", "dietary", "weight loss",
+                "smoking", "tobacco", "screening", "test ", "tests", "procedure",
+                "surgery", "tips for", "living with", "prevention", "wellness",
+            ]),
+            group_keywords: words(&[
+                "cancer", "infection", "mental", "injury", "disorder", "heart", "blood",
+                "nerv", "digestive", "bones", "lung", "brain", "immune",
+            ]),
+            strict: false,
+            min_conditions: 50,
+        }
+    }
+}
+
+impl TopicFilter {
+    /// Whether a topic with this lowercased title and groups counts as a condition
+    fn keeps(&self, title: &str, groups: &[String]) -> bool {
+        let matches = |text: &str, keywords: &[String]| keywords.iter().any(|kw| text.contains(&kw.to_lowercase()));
+        if matches(title, &self.include_keywords) {
+            return true;
+        }
+        if matches(title, &self.exclude_keywords) {
+            return false;
+        }
+        if groups.iter().any(|g| matches(g, &self.group_keywords)) {
+            return true;
+        }
+        !self.strict
+    }
+}
+
+/// MedlinePlus `language` attribute for a language code such as `en`
+pub fn topic_language(lang: &str) -> Result<&'static str, FetchError> {
+    match lang {
+        "en" => Ok("English"),
+        "es" => Ok("Spanish"),
+        other => Err(FetchError::UnsupportedLanguage(other.to_string())),
+    }
+}
+
+/// Location of the extracted conditions for a language inside the data directory
+pub fn metadata_path(data_dir: &Path, lang: &str) -> PathBuf {
+    if lang == "en" {
+        data_dir.join("conditions_metadata.json")
+    } else {
+        data_dir.join(format!("conditions_metadata_{}.json", lang))
+    }
+}
+
+/// File recording which XML and topic filter produced a language's cached conditions
+fn source_hash_path(data_dir: &Path, lang: &str) -> PathBuf {
+    metadata_path(data_dir, lang).with_extension("hash")
+}
+
+/// Hash of the XML and topic filter behind the cached conditions, `None` for caches written before it was recorded
+pub fn source_hash(data_dir: &Path, lang: &str) -> Option<String> {
+    fs::read_to_string(source_hash_path(data_dir, lang)).ok().map(|hash| hash.trim().to_string())
+}
+
+/// Hash identifying the conditions a dump produces, which depend on the XML, the language and the topic filter
+fn hash_source(xml_text: &str, lang: &str, filter: &TopicFilter) -> String {
+    stable_hash([
+        xml_text,
+        lang,
+        &filter.include_keywords.join("\n"),
+        &filter.exclude_keywords.join("\n"),
+        &filter.group_keywords.join("\n"),
+        if filter.strict { "strict" } else { "lenient" },
+    ])
+}
+
+/// FNV-1a hash of the fields, which unlike the std hasher is the same across Rust releases
+pub fn stable_hash<'a>(fields: impl IntoIterator<Item = &'a str>) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for field in fields {
+        for byte in field.bytes().chain(std::iter::once(0)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
 /// Topic information extracted from MedlinePlus XML
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TopicInfo {
@@ -27,233 +192,1075 @@ pub struct ConditionData {
     pub treatments: String,
 }
 
-/// Fetch and parse MedlinePlus data (async wrapper)
-pub async fn fetch_conditions(no_update: bool) -> Result<Vec<ConditionData>, Box<dyn Error + Send + Sync>> {
+/// Download MedlinePlus data, then parse and cache it on a blocking thread.
+/// Returns `None` when nothing changed: no update was asked for, or the download matches the cache.
+pub async fn fetch_conditions(
+    data_dir: &Path,
+    no_update: bool,
+    retries: u32,
+    lang: &str,
+    filter: &TopicFilter,
+) -> Result<Option<Vec<ConditionData>>, FetchError> {
     if no_update {
-        println!("Skipping data fetch (--no-update flag)");
-        return Ok(Vec::new());
+        info!("Skipping data fetch (--no-update flag)");
+        return Ok(None);
     }
+    let language = topic_language(lang)?;
 
+    let xml_text = download_xml(retries).await?;
+
+    let data_dir: PathBuf = data_dir.to_path_buf();
+    let lang = lang.to_string();
+    let filter = filter.clone();
     let result = task::spawn_blocking(move || {
-        fetch_conditions_sync(no_update)
-    }).await?;
+        parse_and_cache(&data_dir, &xml_text, &lang, language, &filter)
+    }).await.map_err(std::io::Error::other)?;
 
     result
 }
 
-/// Synchronous fetch logic
-fn fetch_conditions_sync(no_update: bool) -> Result<Vec<ConditionData>, Box<dyn Error + Send + Sync>> {
-    if no_update {
-        println!("Skipping data fetch (--no-update flag)");
-        return Ok(Vec::new());
+/// What a fetch would produce, counted without writing the cache or embedding anything
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DryRunReport {
+    pub topics_parsed: usize,
+    pub topics_kept: usize,
+    pub duplicates: usize,
+    pub descriptions: usize,
+    pub etiologies: usize,
+    pub manifestations: usize,
+    pub treatments: usize,
+}
+
+impl DryRunReport {
+    /// Rows the embedding tables would receive, one per meaningful section
+    pub fn embeddings(&self) -> usize {
+        self.descriptions + self.etiologies + self.manifestations + self.treatments
     }
+}
+
+/// Parse, filter and split topics into sections without writing anything.
+/// Uses the cached XML when it is younger than `max_age_days` or `no_update` is set, and downloads it otherwise.
+pub async fn dry_run(
+    data_dir: &Path,
+    no_update: bool,
+    max_age_days: i64,
+    retries: u32,
+    lang: &str,
+    filter: &TopicFilter,
+) -> Result<DryRunReport, FetchError> {
+    let language = topic_language(lang)?;
+    let downloaded = if no_update || !needs_fetch(data_dir, lang, max_age_days) {
+        None
+    } else {
+        Some(download_xml(retries).await?)
+    };
+
+    let data_dir: PathBuf = data_dir.to_path_buf();
+    let lang = lang.to_string();
+    let filter = filter.clone();
+    let result = task::spawn_blocking(move || {
+        dry_run_sync(&data_dir, downloaded, &lang, language, &filter)
+    }).await.map_err(std::io::Error::other)?;
+
+    result
+}
+
+/// Count what the downloaded XML, or the cached copy without one, would produce
+fn dry_run_sync(
+    data_dir: &Path,
+    downloaded: Option<String>,
+    lang: &str,
+    language: &str,
+    filter: &TopicFilter,
+) -> Result<DryRunReport, FetchError> {
+    let xml_text = match downloaded {
+        Some(xml_text) => xml_text,
+        None => {
+            let xml_path = data_dir.join(XML_FILE);
+            if !xml_path.exists() {
+                return Err(FetchError::NoCache);
+            }
+            info!("Reading cached {}", xml_path.display());
+            fs::read_to_string(&xml_path)?
+        }
+    };
+
+    let all_topics = parse_topics(&xml_text, lang, language)?;
+    let topics_parsed = all_topics.len();
+    let (conditions, duplicates) = process_topics(all_topics, filter);
+    warn_if_few_conditions(conditions.len(), topics_parsed, filter);
+
+    Ok(DryRunReport {
+        topics_parsed,
+        topics_kept: conditions.len(),
+        duplicates,
+        descriptions: conditions.iter().filter(|c| is_meaningful(&c.description)).count(),
+        etiologies: conditions.iter().filter(|c| is_meaningful(&c.etiology)).count(),
+        manifestations: conditions.iter().filter(|c| is_meaningful(&c.manifestations)).count(),
+        treatments: conditions.iter().filter(|c| is_meaningful(&c.treatments)).count(),
+    })
+}
 
-    println!("Finding latest MedlinePlus XML...");
-    let client = reqwest::blocking::Client::builder()
+/// Turn a downloaded dump into conditions and cache both, or `None` when the cache already holds this dump
+fn parse_and_cache(
+    data_dir: &Path,
+    xml_text: &str,
+    lang: &str,
+    language: &str,
+    filter: &TopicFilter,
+) -> Result<Option<Vec<ConditionData>>, FetchError> {
+    let hash = hash_source(xml_text, lang, filter);
+    if source_hash(data_dir, lang).as_deref() == Some(hash.as_str()) && metadata_path(data_dir, lang).exists() {
+        info!("MedlinePlus data is unchanged since the last download ({}); keeping the cached conditions", hash);
+        // Rewriting the hash restarts the --max-age-days clock without re-parsing anything
+        fs::write(source_hash_path(data_dir, lang), &hash)?;
+        return Ok(None);
+    }
+
+    // Parse before touching the cache so a bad download never replaces good data
+    let all_topics = parse_topics(xml_text, lang, language)?;
+    let topics_parsed = all_topics.len();
+    let (conditions, _) = process_topics(all_topics, filter);
+    warn_if_few_conditions(conditions.len(), topics_parsed, filter);
+    save_cache(data_dir, lang, xml_text, &conditions, &hash)?;
+
+    Ok(Some(conditions))
+}
+
+/// Run the bundled demo topics through the same parse, filter and section pipeline as a fetch,
+/// caching them in `data_dir` like a download would be
+pub fn load_fixture(data_dir: &Path, lang: &str, filter: &TopicFilter) -> Result<Vec<ConditionData>, FetchError> {
+    let language = topic_language(lang)?;
+    info!("Loading the bundled demo topics instead of downloading MedlinePlus");
+
+    let all_topics = parse_topics(DEMO_XML, lang, language)?;
+    if all_topics.is_empty() {
+        return Err(FetchError::Parse(format!("The demo topics are English only; '{}' has none", lang)));
+    }
+    let (conditions, _) = process_topics(all_topics, filter);
+    save_cache(data_dir, lang, DEMO_XML, &conditions, &hash_source(DEMO_XML, lang, filter))?;
+
+    Ok(conditions)
+}
+
+/// Write the XML, the extracted conditions and the hash of their source to the data directory,
+/// replacing the XML atomically
+fn save_cache(
+    data_dir: &Path,
+    lang: &str,
+    xml_text: &str,
+    conditions: &[ConditionData],
+    source_hash: &str,
+) -> Result<(), FetchError> {
+    let xml_path = data_dir.join(XML_FILE);
+    let tmp_path = data_dir.join(format!("{}.tmp", XML_FILE));
+    fs::create_dir_all(data_dir)?;
+    fs::write(&tmp_path, xml_text)?;
+    fs::rename(&tmp_path, &xml_path)?;
+    debug!("XML saved to {}", xml_path.display());
+
+    // Save metadata
+    let metadata_path = metadata_path(data_dir, lang);
+    let metadata_json = serde_json::to_string_pretty(conditions)?;
+    fs::write(&metadata_path, metadata_json)?;
+    debug!("Metadata saved to {}", metadata_path.display());
+
+    // Written last so a hash on disk always describes complete metadata
+    fs::write(source_hash_path(data_dir, lang), source_hash)?;
+
+    Ok(())
+}
+
+/// Download the latest MedlinePlus XML dump, refusing anything that isn't XML
+async fn download_xml(retries: u32) -> Result<String, FetchError> {
+    info!("Finding latest MedlinePlus XML...");
+    let client = reqwest::Client::builder()
         .user_agent("TakeUrMeds/1.0 (+https://github.com/yourname/take_ur_meds)")
         .build()?;
 
-    let latest_xml_url = find_latest_xml_url(&client)?;
-    println!("Downloading: {}", latest_xml_url);
+    let latest_xml_url = find_latest_xml_url(&client, DUMP_BASE_URL, retries).await?;
+    info!("Downloading: {}", latest_xml_url);
 
-    let xml_text = client.get(&latest_xml_url).send()?.text()?;
-    
-    let xml_path = Path::new("data").join("mplus_topics_latest.xml");
-    fs::create_dir_all("data")?;
-    fs::write(&xml_path, &xml_text)?;
-    println!("XML saved to {}", xml_path.display());
-
-    println!("Parsing XML...");
-    let doc = Document::parse_with_options(
-        &xml_text,
-        ParsingOptions {
-            allow_dtd: true,
-            ..Default::default()
-        },
-    )?;
-
-    let root = doc.root_element();
+    let response = send_with_retry(|| client.get(&latest_xml_url), retries).await?.error_for_status()?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_lowercase);
+    if content_type.is_some_and(|ct| !ct.contains("xml")) {
+        return Err(FetchError::NotXml(latest_xml_url));
+    }
+    let xml_text = download_with_progress(response).await?;
+    if looks_like_html(&xml_text) {
+        return Err(FetchError::NotXml(latest_xml_url));
+    }
+
+    Ok(xml_text)
+}
+
+/// Parse the health topics in the requested language out of a MedlinePlus XML dump.
+/// Topics are built one at a time as the XML streams past, so no document tree is held in memory.
+fn parse_topics(xml_text: &str, lang: &str, language: &str) -> Result<Vec<TopicInfo>, FetchError> {
+    let pb = ProgressBar::new_spinner();
+    pb.set_message("Parsing XML...");
+    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+
+    let mut reader = Reader::from_str(xml_text);
     let mut all_topics: Vec<TopicInfo> = Vec::new();
+    let mut topic_nodes = 0;
 
-    for node in root.descendants().filter(|n| n.has_tag_name("health-topic")) {
-        if let Some(title) = node.attribute("title") {
-            if let Some(lang) = node.attribute("language") {
-                if lang != "English" && lang != "en" {
-                    continue;
+    // Element depth, the open topic and its depth, and which of its parts text belongs to
+    let mut depth = 0;
+    let mut topic: Option<(usize, TopicInfo)> = None;
+    let mut summary_parts: Vec<String> = Vec::new();
+    let mut capture = Capture::Nothing;
+    let mut group = String::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                depth += 1;
+                let topic_depth = topic.as_ref().map(|(d, _)| *d);
+                match e.name().as_ref() {
+                    b"health-topic" => {
+                        topic_nodes += 1;
+                        pb.set_message(format!("Parsing XML... {} topics", topic_nodes));
+                        topic = start_topic(&e, lang, language)?.map(|t| (depth, t));
+                        summary_parts.clear();
+                    }
+                    b"full-summary" if topic_depth == Some(depth - 1) => capture = Capture::Summary,
+                    b"group" if topic_depth == Some(depth - 1) => {
+                        capture = Capture::Group;
+                        group.clear();
+                    }
+                    _ => {}
                 }
             }
-
-            let medline_url = node.attribute("url").unwrap_or("").to_string();
-
-            let full_summary = if let Some(summary_node) = node.children().find(|n| n.has_tag_name("full-summary")) {
-                summary_node
-                    .descendants()
-                    .filter_map(|n| n.text())
-                    .collect::<Vec<_>>()
-                    .join("\n")
-                    .replace("\n\n\n", "\n\n")
-                    .trim()
-                    .to_string()
-            } else {
-                String::new()
-            };
-
-            let mut groups = Vec::new();
-            for g in node.children().filter(|n| n.has_tag_name("group")) {
-                if let Some(txt) = g.text() {
-                    groups.push(txt.trim().to_string());
+            Event::Empty(e) if e.name().as_ref() == b"health-topic" => {
+                topic_nodes += 1;
+                all_topics.extend(start_topic(&e, lang, language)?);
+            }
+            Event::Text(t) => match capture {
+                Capture::Summary => summary_parts.push(t.unescape()?.into_owned()),
+                Capture::Group => group.push_str(&t.unescape()?),
+                Capture::Nothing => {}
+            },
+            Event::CData(t) => match capture {
+                Capture::Summary => summary_parts.push(String::from_utf8_lossy(&t).into_owned()),
+                Capture::Group => group.push_str(&String::from_utf8_lossy(&t)),
+                Capture::Nothing => {}
+            },
+            Event::End(e) => {
+                match e.name().as_ref() {
+                    b"full-summary" if capture == Capture::Summary => capture = Capture::Nothing,
+                    b"group" if capture == Capture::Group => {
+                        capture = Capture::Nothing;
+                        if let Some((_, t)) = topic.as_mut() {
+                            t.groups.push(group.trim().to_string());
+                        }
+                    }
+                    b"health-topic" if topic.as_ref().is_some_and(|(d, _)| *d == depth) => {
+                        if let Some((_, mut t)) = topic.take() {
+                            t.full_summary = summary_parts
+                                .join("\n")
+                                .replace("\n\n\n", "\n\n")
+                                .trim()
+                                .to_string();
+                            all_topics.push(t);
+                        }
+                    }
+                    _ => {}
                 }
+                depth = depth.saturating_sub(1);
             }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    pb.finish_and_clear();
 
-            all_topics.push(TopicInfo {
-                title: title.to_string(),
-                medline_url,
-                full_summary,
-                groups,
-            });
+    if topic_nodes == 0 {
+        return Err(FetchError::Parse(
+            "Downloaded XML contains no health topics; keeping the previous cache".to_string(),
+        ));
+    }
+
+    info!("Parsed {} topics.", all_topics.len());
+
+    Ok(all_topics)
+}
+
+/// Which part of the open topic the text being read belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Capture {
+    Nothing,
+    Summary,
+    Group,
+}
+
+/// Topic for a `health-topic` element's attributes, or `None` without a title or in another language
+fn start_topic(element: &BytesStart, lang: &str, language: &str) -> Result<Option<TopicInfo>, FetchError> {
+    let attribute = |name: &str| -> Result<Option<String>, FetchError> {
+        match element.try_get_attribute(name).map_err(quick_xml::Error::from)? {
+            Some(attr) => Ok(Some(attr.unescape_value()?.into_owned())),
+            None => Ok(None),
+        }
+    };
+
+    let Some(title) = attribute("title")? else {
+        return Ok(None);
+    };
+    // Each dump carries every language, so keep only the requested one
+    if let Some(topic_lang) = attribute("language")? {
+        if topic_lang != language && topic_lang != lang {
+            return Ok(None);
         }
     }
 
-    println!("Parsed {} English topics.", all_topics.len());
+    Ok(Some(TopicInfo {
+        title,
+        medline_url: attribute("url")?.unwrap_or_default(),
+        full_summary: String::new(),
+        groups: Vec::new(),
+    }))
+}
 
+/// Filter, dedupe and split topics into sections. Returns the conditions and how many duplicates were dropped.
+fn process_topics(all_topics: Vec<TopicInfo>, filter: &TopicFilter) -> (Vec<ConditionData>, usize) {
     // Filter to diseases, disorders, conditions
+    info!(
+        "Keeping topics with title keywords [{}], dropping [{}], keeping groups [{}]{}",
+        filter.include_keywords.join(", "),
+        filter.exclude_keywords.join(", "),
+        filter.group_keywords.join(", "),
+        if filter.strict { ", dropping everything else" } else { "" }
+    );
     let topics_to_process: Vec<_> = all_topics
         .into_iter()
         .filter(|t| {
-            let title_lower = t.title.to_lowercase();
             let groups_lower: Vec<String> = t.groups.iter().map(|g| g.to_lowercase()).collect();
-
-            if title_lower.contains("disease")
-                || title_lower.contains("disorder")
-                || title_lower.contains("syndrome")
-                || title_lower.contains("cancer")
-                || title_lower.contains("infection")
-                || title_lower.contains("tumor")
-                || title_lower.contains("arthritis")
-                || title_lower.contains("diabetes")
-            {
-                return true;
-            }
-
-            let bad_keywords = [
-                "healthy", "nutrition", "exercise", "dietary", "weight loss",
-                "smoking", "tobacco", "screening", "test ", "tests", "procedure",
-                "surgery", "tips for", "living with", "prevention", "wellness",
-            ];
-            if bad_keywords.iter().any(|&kw| title_lower.contains(kw)) {
-                return false;
-            }
-
-            if groups_lower.iter().any(|g| {
-                g.contains("cancer")
-                    || g.contains("infection")
-                    || g.contains("mental")
-                    || g.contains("injury")
-                    || g.contains("disorder")
-                    || g.contains("heart")
-                    || g.contains("blood")
-                    || g.contains("nerv")
-                    || g.contains("digestive")
-                    || g.contains("bones")
-                    || g.contains("lung")
-                    || g.contains("brain")
-                    || g.contains("immune")
-            }) {
-                return true;
-            }
-
-            true
+            filter.keeps(&t.title.to_lowercase(), &groups_lower)
         })
         .collect();
 
+    let (topics_to_process, duplicates) = dedupe_topics(topics_to_process);
+    info!(
+        "Kept {} condition topics ({} duplicates dropped).",
+        topics_to_process.len(),
+        duplicates
+    );
+
     // Extract sections for each condition
+    let mut shared = 0;
     let conditions: Vec<ConditionData> = topics_to_process
         .into_iter()
         .map(|topic| {
-            let (description, etiology, manifestations, treatments) =
-                extract_sections(&topic.full_summary);
+            let sections = extract_sections(&topic.full_summary);
+            if sections.shared_paragraph {
+                shared += 1;
+            }
 
             ConditionData {
                 name: topic.title,
                 medline_url: topic.medline_url,
                 groups: topic.groups,
-                description,
-                etiology,
-                manifestations,
-                treatments,
+                description: sections.description,
+                etiology: sections.etiology,
+                manifestations: sections.manifestations,
+                treatments: sections.treatments,
             }
         })
         .collect();
+    info!(
+        "{} of {} conditions have a single paragraph shared by the description and another section.",
+        shared,
+        conditions.len()
+    );
 
-    // Save metadata
-    let metadata_path = Path::new("data").join("conditions_metadata.json");
-    let metadata_json = serde_json::to_string_pretty(&conditions)?;
-    fs::write(&metadata_path, metadata_json)?;
-    println!("Metadata saved to {}", metadata_path.display());
+    (conditions, duplicates)
+}
 
-    Ok(conditions)
+/// Warn when the filter kept suspiciously few of the parsed topics
+fn warn_if_few_conditions(kept: usize, parsed: usize, filter: &TopicFilter) {
+    if kept >= filter.min_conditions {
+        return;
+    }
+    warn!(
+        "Only {} of {} MedlinePlus topics were kept as conditions (expected at least {}), so search results will be poor. \
+         Check the [topics] keywords in shiroyaku.toml and that https://medlineplus.gov/xml still serves the health topics dump.",
+        kept, parsed, filter.min_conditions
+    );
 }
 
-/// Extract sections from full summary
-fn extract_sections(summary: &str) -> (String, String, String, String) {
-    if summary.trim().is_empty() {
-        return (
-            "No summary available".to_string(),
-            "N/A".to_string(),
-            "N/A".to_string(),
-            "N/A".to_string(),
-        );
+/// Collapse topics that share a title, keeping the one with the longest summary.
+/// Returns the unique topics in first-seen order and how many were dropped.
+fn dedupe_topics(topics: Vec<TopicInfo>) -> (Vec<TopicInfo>, usize) {
+    let mut index_by_title: HashMap<String, usize> = HashMap::new();
+    let mut unique: Vec<TopicInfo> = Vec::new();
+    let mut dropped = 0;
+
+    for topic in topics {
+        let key = topic.title.trim().to_lowercase();
+        match index_by_title.get(&key) {
+            Some(&i) => {
+                dropped += 1;
+                if topic.full_summary.len() > unique[i].full_summary.len() {
+                    unique[i] = topic;
+                }
+            }
+            None => {
+                index_by_title.insert(key, unique.len());
+                unique.push(topic);
+            }
+        }
+    }
+
+    (unique, dropped)
+}
+
+/// Summary text split into the parts that get embedded separately
+#[derive(Debug, Clone, PartialEq)]
+struct Sections {
+    description: String,
+    etiology: String,
+    manifestations: String,
+    treatments: String,
+    /// The description repeats the only paragraph of another section
+    shared_paragraph: bool,
+}
+
+/// A block of summary text and the heading it appeared under
+struct Paragraph {
+    heading: String,
+    text: String,
+}
+
+// English and Spanish keywords, since summaries come in either language
+const ETIOLOGY_KEYWORDS: &[&str] = &["cause", "caused by", "etiology", "risk factor", "causa", "factor de riesgo"];
+const MANIFESTATION_KEYWORDS: &[&str] = &["symptom", "sign", "manifestation", "present with", "síntoma", "signo"];
+const TREATMENT_KEYWORDS: &[&str] = &[
+    "treat", "therapy", "treatment", "medication", "surgery", "tratamiento", "terapia", "medicamento", "cirugía",
+];
+
+/// Description used when a topic has no summary
+const NO_SUMMARY: &str = "No summary available";
+
+/// Placeholder for a section the summary says nothing about
+const NOT_AVAILABLE: &str = "N/A";
+
+/// Placeholders written by this and earlier versions of the section extraction
+const SECTION_PLACEHOLDERS: &[&str] = &[NO_SUMMARY, NOT_AVAILABLE, "Details integrated in the description above."];
+
+/// Shortest section text worth embedding or showing
+const MIN_SECTION_CHARS: usize = 20;
+
+/// Whether a section holds real text rather than a placeholder or a fragment
+pub fn is_meaningful(section: &str) -> bool {
+    let section = section.trim();
+    !SECTION_PLACEHOLDERS.iter().any(|p| section.eq_ignore_ascii_case(p))
+        && section.chars().count() >= MIN_SECTION_CHARS
+}
+
+/// Split a MedlinePlus summary into paragraphs and assign whole paragraphs to sections
+fn extract_sections(summary_html: &str) -> Sections {
+    let paragraphs = split_paragraphs(summary_html);
+    if paragraphs.is_empty() {
+        return Sections {
+            description: NO_SUMMARY.to_string(),
+            etiology: NOT_AVAILABLE.to_string(),
+            manifestations: NOT_AVAILABLE.to_string(),
+            treatments: NOT_AVAILABLE.to_string(),
+            shared_paragraph: false,
+        };
+    }
+
+    // Each paragraph goes to exactly one section: 0 description, 1 etiology, 2 manifestations, 3 treatments
+    let mut assigned = Vec::with_capacity(paragraphs.len());
+    for paragraph in &paragraphs {
+        // Keyword hits in the paragraph decide its section; paragraphs without any
+        // follow their heading, e.g. a symptom list under "What are the symptoms?"
+        let lower = paragraph.text.to_lowercase();
+        let by_text = section_hits(&lower);
+        let hits = if by_text.iter().any(|&n| n > 0) {
+            by_text
+        } else {
+            section_hits(&paragraph.heading)
+        };
+
+        let best = hits.iter().copied().max().unwrap_or(0);
+        let section = if best == 0 {
+            0
+        } else {
+            hits.iter().position(|&n| n == best).map_or(0, |i| i + 1)
+        };
+        assigned.push(section);
+    }
+
+    // Summaries that are all about one section still need an overview. The first paragraph
+    // moves to the description when its section keeps others; a lone paragraph is shared.
+    let mut shared_paragraph = false;
+    if !assigned.contains(&0) {
+        let first_section = assigned[0];
+        if assigned.iter().filter(|&&s| s == first_section).count() > 1 {
+            assigned[0] = 0;
+        } else {
+            shared_paragraph = true;
+        }
+    }
+
+    let section_text = |section: usize| {
+        let parts: Vec<&str> = paragraphs
+            .iter()
+            .zip(&assigned)
+            .filter(|(_, &s)| s == section)
+            .map(|(p, _)| p.text.as_str())
+            .collect();
+        if parts.is_empty() { NOT_AVAILABLE.to_string() } else { parts.join("\n\n") }
+    };
+    Sections {
+        description: if shared_paragraph { paragraphs[0].text.clone() } else { section_text(0) },
+        etiology: section_text(1),
+        manifestations: section_text(2),
+        treatments: section_text(3),
+        shared_paragraph,
+    }
+}
+
+/// Split summary HTML into plain-text paragraphs, tracking the most recent heading
+fn split_paragraphs(html: &str) -> Vec<Paragraph> {
+    let mut paragraphs = Vec::new();
+    let mut heading = String::new();
+    let mut current = String::new();
+    let mut in_heading = false;
+    let mut rest = html;
+
+    while let Some(open) = rest.find('<') {
+        current.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('>') else {
+            current.push_str(&rest[open..]);
+            rest = "";
+            break;
+        };
+        let tag = rest[open + 1..open + close].trim().to_lowercase();
+        rest = &rest[open + close + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("");
+        let is_heading = matches!(name, "h1" | "h2" | "h3" | "h4" | "h5" | "h6");
+
+        if is_heading {
+            if closing {
+                heading = normalize_text(&current);
+                current.clear();
+                in_heading = false;
+            } else {
+                push_paragraph(&mut current, &heading, &mut paragraphs);
+                in_heading = true;
+            }
+        } else if !in_heading && matches!(name, "p" | "div" | "ul" | "ol" | "br") {
+            push_paragraph(&mut current, &heading, &mut paragraphs);
+        } else if name == "li" && !closing {
+            current.push_str(" \u{2022} ");
+        }
     }
+    current.push_str(rest);
 
-    let lower = summary.to_lowercase();
-    let first_part = summary.lines().take(20).collect::<Vec<_>>().join("\n");
+    // Plain-text summaries have no tags, so fall back to blank-line breaks
+    for block in current.split("\n\n") {
+        let mut block = block.to_string();
+        push_paragraph(&mut block, &heading, &mut paragraphs);
+    }
 
-    let etiology = extract_section(&lower, summary, &["cause", "caused by", "etiology", "risk factor"]);
-    let manifestations =
-        extract_section(&lower, summary, &["symptom", "sign", "manifestation", "present with"]);
-    let treatments =
-        extract_section(&lower, summary, &["treat", "therapy", "treatment", "medication", "surgery"]);
+    paragraphs
+}
 
-    (first_part, etiology, manifestations, treatments)
+/// Move accumulated text into a new paragraph under the current heading
+fn push_paragraph(current: &mut String, heading: &str, paragraphs: &mut Vec<Paragraph>) {
+    let text = normalize_text(current);
+    if !text.is_empty() {
+        paragraphs.push(Paragraph { heading: heading.to_lowercase(), text });
+    }
+    current.clear();
 }
 
-fn extract_section(lower: &str, original: &str, keywords: &[&str]) -> String {
-    for &kw in keywords {
-        if let Some(pos) = lower.find(kw) {
-            let start = if pos > 100 { pos - 100 } else { 0 };
-            let slice = &original[start..];
-            if let Some(end) = slice.find("\n\n") {
-                return slice[..end].trim().to_string();
+/// Decode HTML entities, drop tags left behind by escaped markup, and collapse whitespace,
+/// putting list items on their own lines
+fn normalize_text(text: &str) -> String {
+    let decoded = strip_tags(&decode_entities(text));
+
+    decoded
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace(" \u{2022} ", "\n\u{2022} ")
+}
+
+/// Named entities MedlinePlus summaries use, beyond the numeric `&#8217;` and `&#x2019;` forms
+const NAMED_ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("apos", '\''),
+    ("nbsp", ' '),
+    ("ndash", '\u{2013}'),
+    ("mdash", '\u{2014}'),
+    ("lsquo", '\u{2018}'),
+    ("rsquo", '\u{2019}'),
+    ("ldquo", '\u{201C}'),
+    ("rdquo", '\u{201D}'),
+    ("hellip", '\u{2026}'),
+    ("bull", '\u{2022}'),
+    ("deg", '\u{00B0}'),
+    ("reg", '\u{00AE}'),
+    ("copy", '\u{00A9}'),
+    ("trade", '\u{2122}'),
+];
+
+/// Decode named and numeric HTML entities in one pass, so "&amp;lt;" becomes "&lt;" rather than "<".
+/// Unknown entities are left as they are.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let ch = entity.and_then(|name| {
+            if let Some(hex) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+            } else if let Some(dec) = name.strip_prefix('#') {
+                dec.parse().ok().and_then(char::from_u32)
             } else {
-                return slice.lines().take(15).collect::<Vec<_>>().join("\n");
+                NAMED_ENTITIES.iter().find(|(n, _)| *n == name).map(|&(_, c)| c)
+            }
+        });
+
+        match (entity, ch) {
+            (Some(name), Some(c)) => {
+                decoded.push(c);
+                rest = &rest[name.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
             }
         }
     }
-    "Details integrated in the description above.".to_string()
+    decoded.push_str(rest);
+
+    decoded
 }
 
-fn find_latest_xml_url(client: &reqwest::blocking::Client) -> Result<String, Box<dyn Error + Send + Sync>> {
+/// Remove tags such as `<b>` or `</a>` that only appear once escaped markup is decoded.
+/// A `<` not followed by a tag name, as in "levels < 70", is kept.
+fn strip_tags(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(open) = rest.find('<') {
+        stripped.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let is_tag = after.trim_start_matches('/').starts_with(|c: char| c.is_ascii_alphabetic());
+        match after.find('>').filter(|_| is_tag) {
+            Some(close) => {
+                stripped.push(' ');
+                rest = &after[close + 1..];
+            }
+            None => {
+                stripped.push('<');
+                rest = after;
+            }
+        }
+    }
+    stripped.push_str(rest);
+
+    stripped
+}
+
+/// Keyword hits for etiology, manifestations and treatments, in that order
+fn section_hits(lower: &str) -> [usize; 3] {
+    [
+        count_keywords(lower, ETIOLOGY_KEYWORDS),
+        count_keywords(lower, MANIFESTATION_KEYWORDS),
+        count_keywords(lower, TREATMENT_KEYWORDS),
+    ]
+}
+
+/// Count keyword occurrences that start at a word boundary, so "sign" doesn't match "design"
+fn count_keywords(lower: &str, keywords: &[&str]) -> usize {
+    keywords
+        .iter()
+        .map(|kw| {
+            lower
+                .match_indices(kw)
+                .filter(|(pos, _)| {
+                    lower[..*pos]
+                        .chars()
+                        .next_back()
+                        .is_none_or(|c| !c.is_alphanumeric())
+                })
+                .count()
+        })
+        .sum()
+}
+
+/// Read a response body while showing progress, as a byte bar when the size is known.
+/// A transfer that stalls for `STALL_TIMEOUT` is abandoned rather than left hanging.
+async fn download_with_progress(
+    mut response: reqwest::Response,
+) -> Result<String, FetchError> {
+    let pb = match response.content_length() {
+        Some(len) => {
+            let pb = ProgressBar::new(len);
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template("{msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+                    .unwrap()
+                    .progress_chars("#>-"),
+            );
+            pb
+        }
+        None => {
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(ProgressStyle::default_spinner().template("{msg} {spinner:.green} {bytes}").unwrap());
+            pb.enable_steady_tick(std::time::Duration::from_millis(100));
+            pb
+        }
+    };
+    pb.set_message("Downloading");
+
+    let mut body = Vec::new();
+    while let Some(chunk) = tokio::time::timeout(STALL_TIMEOUT, response.chunk())
+        .await
+        .map_err(|_| FetchError::Timeout(STALL_TIMEOUT.as_secs()))??
+    {
+        pb.inc(chunk.len() as u64);
+        body.extend_from_slice(&chunk);
+    }
+    pb.finish_and_clear();
+
+    Ok(String::from_utf8(body)?)
+}
+
+/// Check whether a body is an HTML page, such as an error page served in place of the dump
+fn looks_like_html(body: &str) -> bool {
+    let start = body.trim_start().chars().take(15).collect::<String>().to_lowercase();
+    start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+/// Probe the last week of dated dump URLs under `base_url` and return the newest one that exists
+async fn find_latest_xml_url(
+    client: &reqwest::Client,
+    base_url: &str,
+    retries: u32,
+) -> Result<String, FetchError> {
     let today = Local::now().date_naive();
     for i in 0..7 {
         let date = today - Duration::days(i);
-        let candidate = format!("https://medlineplus.gov/xml/mplus_topics_{}.xml", date.format("%Y-%m-%d"));
-        if client.head(&candidate).send().is_ok() {
-            return Ok(candidate);
+        let candidate = format!("{}/mplus_topics_{}.xml", base_url, date.format("%Y-%m-%d"));
+        // A completed request can still be a 404, so only accept a successful status
+        if let Ok(resp) = send_with_retry(|| client.head(&candidate), retries).await {
+            if resp.status().is_success() {
+                return Ok(candidate);
+            }
         }
     }
-    // Fallback
-    Ok("https://medlineplus.gov/xml/mplus_topics_2026-02-25.xml".to_string())
+    Err(FetchError::NoRecentDump)
+}
+
+/// Send a request, retrying timeouts, connection failures and 5xx responses
+/// with exponential backoff (1s, 2s, 4s, ...). Other responses such as a 404
+/// are returned straight away. Each attempt waits at most `REQUEST_TIMEOUT` for a response.
+async fn send_with_retry(
+    build: impl Fn() -> reqwest::RequestBuilder,
+    retries: u32,
+) -> Result<reqwest::Response, FetchError> {
+    let mut attempt = 0;
+    loop {
+        let result = match tokio::time::timeout(REQUEST_TIMEOUT, build().send()).await {
+            Ok(result) => result.map_err(FetchError::from),
+            Err(_) => Err(FetchError::Timeout(REQUEST_TIMEOUT.as_secs())),
+        };
+        let failure = match &result {
+            Ok(resp) if resp.status().is_server_error() => Some(format!("HTTP {}", resp.status())),
+            Ok(_) => None,
+            Err(FetchError::Http(e)) if e.is_timeout() || e.is_connect() || e.is_request() => Some(e.to_string()),
+            Err(e @ FetchError::Timeout(_)) => Some(e.to_string()),
+            Err(_) => None,
+        };
+
+        match failure {
+            Some(reason) if attempt < retries => {
+                let delay = std::time::Duration::from_secs(1 << attempt);
+                attempt += 1;
+                warn!(
+                    "Request failed ({}), retrying in {}s (attempt {}/{})",
+                    reason,
+                    delay.as_secs(),
+                    attempt,
+                    retries
+                );
+                tokio::time::sleep(delay).await;
+            }
+            _ => return result,
+        }
+    }
+}
+
+/// Check whether the cached MedlinePlus data is missing or was last checked against MedlinePlus
+/// at least `max_age_days` ago; 0 always refetches
+pub fn needs_fetch(data_dir: &Path, lang: &str, max_age_days: i64) -> bool {
+    let xml_path = data_dir.join(XML_FILE);
+    if !xml_path.exists() {
+        return true;
+    }
+    
+    let metadata_path = metadata_path(data_dir, lang);
+    if !metadata_path.exists() {
+        return true;
+    }
+    
+    // The hash is rewritten whenever a download turns out unchanged, so it dates the last check
+    let hash_path = source_hash_path(data_dir, lang);
+    let checked_path = if hash_path.exists() { hash_path } else { metadata_path };
+    if let Ok(metadata) = fs::metadata(&checked_path) {
+        if let Ok(modified) = metadata.modified() {
+            let modified_time = chrono::DateTime::<chrono::Utc>::from(modified);
+            let now = chrono::Utc::now();
+            let days_since_update = (now - modified_time).num_days();
+            debug!("Cached data is {} days old; refreshing at {} days", days_since_update, max_age_days);
+            
+            if days_since_update >= max_age_days {
+                return true;
+            }
+        }
+    }
+    
+    false
 }
 
 /// Load conditions from saved metadata
-pub fn load_conditions() -> Result<Vec<ConditionData>, Box<dyn Error + Send + Sync>> {
-    let metadata_path = Path::new("data").join("conditions_metadata.json");
+pub fn load_conditions(data_dir: &Path, lang: &str) -> Result<Vec<ConditionData>, FetchError> {
+    let metadata_path = metadata_path(data_dir, lang);
     if metadata_path.exists() {
         let content = fs::read_to_string(&metadata_path)?;
         let conditions: Vec<ConditionData> = serde_json::from_str(&content)?;
-        println!("Loaded {} conditions from cache", conditions.len());
+        info!("Loaded {} conditions from cache", conditions.len());
         Ok(conditions)
     } else {
-        Err("No cached data found".into())
+        Err(FetchError::NoCache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn topic(title: &str, summary: &str) -> TopicInfo {
+        TopicInfo {
+            title: title.to_string(),
+            medline_url: format!("https://medlineplus.gov/{}.html", summary.len()),
+            full_summary: summary.to_string(),
+            groups: Vec::new(),
+        }
+    }
+
+    /// Serve HTTP on a local port, answering every request with the status `status_for` picks for its path.
+    /// Returns the server's base URL.
+    async fn mock_server(status_for: impl Fn(&str) -> u16 + Send + 'static) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let path = request.split_whitespace().nth(1).unwrap_or("/");
+                let response = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status_for(path)
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        base_url
+    }
+
+    #[tokio::test]
+    async fn missing_dumps_for_the_whole_week_are_an_error() {
+        let base_url = mock_server(|_| 404).await;
+        let result = find_latest_xml_url(&reqwest::Client::new(), &base_url, 0).await;
+        assert!(matches!(result, Err(FetchError::NoRecentDump)), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn only_a_dump_that_answers_200_is_chosen() {
+        let date = (Local::now().date_naive() - Duration::days(3)).format("%Y-%m-%d").to_string();
+        let path = format!("/mplus_topics_{}.xml", date);
+        let base_url = mock_server(move |p| if p == path { 200 } else { 404 }).await;
+
+        let url = find_latest_xml_url(&reqwest::Client::new(), &base_url, 0).await.unwrap();
+        assert_eq!(url, format!("{}/mplus_topics_{}.xml", base_url, date));
+    }
+
+    #[test]
+    fn duplicate_titles_keep_the_longest_summary() {
+        let topics = vec![
+            topic("Asthma", "Short."),
+            topic("Migraine", "Headaches."),
+            topic("  ASTHMA ", "A much longer summary of asthma."),
+            topic("asthma", "Medium summary."),
+        ];
+
+        let (unique, dropped) = dedupe_topics(topics);
+        assert_eq!(dropped, 2);
+        let summaries: Vec<&str> = unique.iter().map(|t| t.full_summary.as_str()).collect();
+        assert_eq!(summaries, ["A much longer summary of asthma.", "Headaches."]);
+    }
+
+    /// Shaped like a MedlinePlus full-summary: a heading and paragraph or list per question
+    const ASTHMA_SUMMARY: &str = "<h3>What is asthma?</h3>\
+        <p>Asthma is a chronic disease that affects your airways. Your airways are tubes that carry air in and out of your lungs.</p>\
+        <h3>What causes asthma?</h3>\
+        <p>The exact cause of asthma is unknown. Genetics and your environment probably play a role in who gets asthma.</p>\
+        <h3>What are the symptoms of asthma?</h3>\
+        <ul><li>Wheezing</li><li>Coughing, especially early in the morning or at night</li><li>Chest tightness</li></ul>\
+        <h3>What are the treatments for asthma?</h3>\
+        <p>Treatment includes quick-relief medication for attacks and long-term control medication taken every day.</p>";
+
+    #[test]
+    fn summary_paragraphs_go_to_their_sections() {
+        let sections = extract_sections(ASTHMA_SUMMARY);
+        assert_eq!(
+            sections.description,
+            "Asthma is a chronic disease that affects your airways. Your airways are tubes that carry air in and out of your lungs."
+        );
+        assert!(sections.etiology.starts_with("The exact cause of asthma is unknown."));
+        assert_eq!(
+            sections.manifestations,
+            "\u{2022} Wheezing\n\u{2022} Coughing, especially early in the morning or at night\n\u{2022} Chest tightness"
+        );
+        assert!(sections.treatments.starts_with("Treatment includes quick-relief medication"));
+        assert!(!sections.shared_paragraph);
+    }
+
+    #[test]
+    fn sections_never_share_a_paragraph() {
+        // Later paragraphs mention words of other sections, which once put them in several
+        let summary = "<p>Gout is a common form of arthritis that affects the joints.</p>\
+            <p>Gout is caused by uric acid crystals, and symptoms follow when they build up.</p>\
+            <p>Symptoms include sudden pain and swelling in a joint; the cause is often unclear.</p>\
+            <p>Treatment with medicine relieves symptoms and prevents attacks caused by uric acid.</p>";
+        let sections = extract_sections(summary);
+        let paragraphs: Vec<String> = split_paragraphs(summary).into_iter().map(|p| p.text).collect();
+        
+        let texts = [&sections.description, &sections.etiology, &sections.manifestations, &sections.treatments];
+        for paragraph in &paragraphs {
+            let holders = texts.iter().filter(|t| t.split("\n\n").any(|part| part == paragraph)).count();
+            assert_eq!(holders, 1, "{:?} is in {} sections", paragraph, holders);
+        }
+        let parts: usize = texts.iter().filter(|t| t.as_str() != NOT_AVAILABLE).map(|t| t.split("\n\n").count()).sum();
+        assert_eq!(parts, paragraphs.len());
+    }
+
+    #[test]
+    fn single_paragraph_summary_is_shared_with_the_description() {
+        let summary = "<p>Signs and symptoms of a sprain include pain, swelling and bruising around the joint.</p>";
+        let sections = extract_sections(summary);
+        assert_eq!(sections.manifestations, "Signs and symptoms of a sprain include pain, swelling and bruising around the joint.");
+        assert_eq!(sections.description, sections.manifestations);
+        assert_eq!(sections.etiology, NOT_AVAILABLE);
+        assert!(sections.shared_paragraph);
+    }
+
+    #[test]
+    fn empty_summary_gets_placeholders() {
+        let sections = extract_sections("  ");
+        assert_eq!(sections.description, NO_SUMMARY);
+        assert_eq!(sections.treatments, NOT_AVAILABLE);
+    }
+
+    #[test]
+    fn streaming_parser_finds_every_fixture_topic() {
+        // The same count a DOM walk over every English <health-topic> element gives
+        let english = DEMO_XML.matches("<health-topic ").count();
+        assert_eq!(english, DEMO_XML.matches("language=\"English\"").count());
+
+        let topics = parse_topics(DEMO_XML, "en", "English").unwrap();
+        assert_eq!(topics.len(), english);
+        assert!(topics.iter().all(|t| !t.full_summary.is_empty() && !t.groups.is_empty()));
+        assert_eq!(topics[0].title, "Influenza Infection");
+        assert_eq!(topics[0].groups, ["Infections"]);
+    }
+
+    #[test]
+    fn streaming_parser_keeps_the_requested_language_and_direct_children() {
+        let xml = r#"<health-topics>
+            <health-topic title="Gout" url="https://example.org/gout" language="English">
+                <also-called>Gouty arthritis</also-called>
+                <full-summary>&lt;p&gt;Gout is a form of arthritis.&lt;/p&gt;</full-summary>
+                <site title="Elsewhere"><full-summary>Not this summary</full-summary><group>Not this group</group></site>
+                <group>Bones, Joints and Muscles</group>
+            </health-topic>
+            <health-topic title="Gota" url="https://example.org/gota" language="Spanish">
+                <full-summary>La gota es una forma de artritis.</full-summary>
+            </health-topic>
+            <health-topic title="Empty Topic" url="https://example.org/empty" language="English"/>
+        </health-topics>"#;
+
+        let topics = parse_topics(xml, "en", "English").unwrap();
+        let titles: Vec<&str> = topics.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, ["Gout", "Empty Topic"]);
+        assert_eq!(topics[0].full_summary, "<p>Gout is a form of arthritis.</p>");
+        assert_eq!(topics[0].groups, ["Bones, Joints and Muscles"]);
+        assert_eq!(parse_topics(xml, "es", "Spanish").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn entities_are_decoded_once() {
+        assert_eq!(decode_entities("Crohn&#8217;s &amp; colitis"), "Crohn\u{2019}s & colitis");
+        assert_eq!(decode_entities("&ldquo;flu&rdquo; &#x2013; fever&nbsp;38&deg;C"), "\u{201C}flu\u{201D} \u{2013} fever 38\u{00B0}C");
+        // Double-encoded markup stays text, and unknown or unterminated entities are kept
+        assert_eq!(decode_entities("&amp;lt;b&amp;gt;"), "&lt;b&gt;");
+        assert_eq!(decode_entities("AT&T &bogus; R&D"), "AT&T &bogus; R&D");
+    }
+
+    #[test]
+    fn nested_and_escaped_tags_leave_clean_prose() {
+        let summary = "<h3>What is it?</h3>\
+            <p>Eczema is an <b>itchy <i>skin</i></b> condition that <a href=\"https://example.org\">affects</a> \
+            about 1 in 10 people&#8217;s skin.</p>\
+            <p>Moisturizers &amp; creams &lt;em&gt;help&lt;/em&gt; treat it when sugar levels &lt; 70 are ruled out.</p>";
+        let sections = extract_sections(summary);
+        assert_eq!(
+            sections.description,
+            "Eczema is an itchy skin condition that affects about 1 in 10 people\u{2019}s skin."
+        );
+        assert_eq!(sections.treatments, "Moisturizers & creams help treat it when sugar levels < 70 are ruled out.");
+    }
+
+    #[test]
+    fn placeholders_and_fragments_are_not_meaningful() {
+        for sentinel in ["N/A", "n/a", "No summary available", "Details integrated in the description above.", "  N/A  "] {
+            assert!(!is_meaningful(sentinel), "{:?} counted as text", sentinel);
+        }
+        for fragment in ["", "   ", "See below.", "Rest and fluids."] {
+            assert!(!is_meaningful(fragment), "{:?} counted as text", fragment);
+        }
+        assert!(is_meaningful("Rest, fluids and pain relievers help."));
+        assert!(is_meaningful("N/A is not all this paragraph says about it."));
     }
 }
@@ -0,0 +1,81 @@
+use anyhow::Result;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::search::RankedCondition;
+use crate::ui;
+
+/// Number of entries shown by --history
+pub const HISTORY_DISPLAY_LIMIT: usize = 20;
+
+/// One past search and its best match
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: String,
+    pub query: String,
+    pub top_condition: Option<String>,
+    pub score: Option<f32>,
+}
+
+/// Location of the search history inside the data directory
+pub fn history_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("search_history.jsonl")
+}
+
+/// Append a search and its top result to the history file
+pub fn append(data_dir: &Path, query: &str, results: &[RankedCondition]) -> Result<()> {
+    let top = results.first();
+    let entry = HistoryEntry {
+        timestamp: Local::now().to_rfc3339(),
+        query: query.to_string(),
+        top_condition: top.map(|c| c.name.clone()),
+        score: top.map(|c| c.score),
+    };
+    
+    fs::create_dir_all(data_dir)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_path(data_dir))?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+/// Load the history, oldest first, skipping lines that don't parse
+pub fn load(data_dir: &Path) -> Vec<HistoryEntry> {
+    let Ok(content) = fs::read_to_string(history_path(data_dir)) else {
+        return Vec::new();
+    };
+    
+    content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Display the most recent searches, newest first
+pub fn display_history(entries: &[HistoryEntry]) {
+    if entries.is_empty() {
+        println!("\nNo search history yet.\n");
+        return;
+    }
+    
+    println!("\n═══════════════════════════════════════════════════════════════");
+    println!("{:^63}", "RECENT SEARCHES");
+    println!("═══════════════════════════════════════════════════════════════\n");
+    
+    for entry in entries.iter().rev().take(HISTORY_DISPLAY_LIMIT) {
+        let timestamp = entry.timestamp.get(..16).unwrap_or(&entry.timestamp).replace('T', " ");
+        println!("{}  {}", timestamp, entry.query);
+        match (&entry.top_condition, entry.score) {
+            (Some(name), Some(score)) => {
+                println!("   Top match: {} ({})", ui::condition_name(name), ui::score(score))
+            }
+            _ => println!("   No matches"),
+        }
+    }
+    println!();
+}
@@ -0,0 +1,15 @@
+pub mod cli;
+pub mod embedding;
+pub mod engine;
+pub mod export;
+pub mod fetch;
+pub mod history;
+pub mod response;
+pub mod saved;
+pub mod search;
+pub mod server;
+pub mod tui;
+pub mod ui;
+
+pub use engine::SearchEngine;
+pub use search::RankedCondition;
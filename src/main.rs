@@ -1,130 +1,174 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-pub mod embedding;
-pub mod fetch;
-pub mod search;
-pub mod ui;
-
-use serde::{Deserialize, Serialize};
+use shiroyaku::embedding::{ExecutionProvider, ModelChoice};
+use shiroyaku::engine::validate_query;
+use shiroyaku::response::RankedConditionOut;
+use shiroyaku::search::Weights;
+use shiroyaku::{cli, embedding, fetch, ui, SearchEngine};
+use std::path::PathBuf;
+use std::sync::Arc;
 use tauri::Manager;
+use tokio::sync::Mutex;
+
+/// Search engine shared across commands so the model is only loaded once
+#[derive(Default)]
+struct EngineState(Mutex<Option<Arc<SearchEngine>>>);
 
-/// Search result for frontend
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SearchResult {
-    pub rank: usize,
-    pub name: String,
-    pub score: f32,
-    pub description_matches: usize,
-    pub etiology_matches: usize,
-    pub manifestation_matches: usize,
-    pub description_text: Option<String>,
-    pub etiology_text: Option<String>,
-    pub manifestation_text: Option<String>,
+impl EngineState {
+    /// Open the engine on first use and hand back the shared instance
+    async fn get(&self, config: &AppConfig) -> Result<Arc<SearchEngine>, String> {
+        let mut slot = self.0.lock().await;
+        if let Some(engine) = slot.as_ref() {
+            return Ok(engine.clone());
+        }
+        
+        log::info!("Loading embedding model...");
+        let engine = SearchEngine::open(&config.data_dir, config.model, config.execution_provider)
+            .await
+            .map_err(|e| format!("Error loading model: {}", e))?;
+        let engine = cli::configure_engine(engine, &config.cli, config.weights);
+        let engine = Arc::new(engine);
+        *slot = Some(engine.clone());
+        Ok(engine)
+    }
+}
+
+/// Settings from the command line and config file that the window's commands need
+struct AppConfig {
+    data_dir: PathBuf,
+    model: ModelChoice,
+    execution_provider: ExecutionProvider,
+    lang: String,
+    fetch_retries: u32,
+    max_age_days: i64,
+    topic_filter: fetch::TopicFilter,
+    weights: Weights,
+    /// Search settings, applied to the engine the same way the terminal applies them
+    cli: cli::Cli,
 }
 
 /// Check if database is ready
 #[tauri::command]
-async fn check_database() -> Result<bool, String> {
-    let has_embeddings = embedding::has_embeddings().await;
+async fn check_database(config: tauri::State<'_, AppConfig>) -> Result<bool, String> {
+    let has_embeddings = embedding::has_embeddings(&config.data_dir, config.model, &config.lang).await;
     Ok(has_embeddings)
 }
 
 /// Initialize database (fetch and embed if needed)
 #[tauri::command]
-async fn initialize_database(no_update: bool) -> Result<String, String> {
-    let needs_fresh_data = !no_update && needs_fetch();
+async fn initialize_database(
+    config: tauri::State<'_, AppConfig>,
+    state: tauri::State<'_, EngineState>,
+    no_update: bool,
+) -> Result<String, String> {
+    if no_update {
+        return Ok("Database initialized".to_string());
+    }
     
-    if needs_fresh_data {
-        let conditions = fetch::fetch_conditions(no_update)
+    // Stale or missing data is downloaded again; a download that matches the cache changes nothing
+    let fetched = if fetch::needs_fetch(&config.data_dir, &config.lang, config.max_age_days) {
+        fetch::fetch_conditions(&config.data_dir, false, config.fetch_retries, &config.lang, &config.topic_filter)
             .await
-            .map_err(|e| format!("Error fetching conditions: {}", e))?;
-        
-        if !conditions.is_empty() {
-            let mut model = fastembed::TextEmbedding::try_new(
-                fastembed::InitOptions::new(fastembed::EmbeddingModel::EmbeddingGemma300M),
-            ).map_err(|e| format!("Error loading model: {}", e))?;
-            
-            embedding::embed_conditions(conditions, &mut model)
-                .await
-                .map_err(|e| format!("Error embedding: {}", e))?;
+            .map_err(|e| format!("Error fetching conditions: {}", e))?
+    } else {
+        None
+    };
+    // Unchanged cached data only needs embedding when nothing is stored yet
+    let conditions = match fetched {
+        Some(conditions) => Some(conditions),
+        None if !embedding::has_embeddings(&config.data_dir, config.model, &config.lang).await => {
+            Some(fetch::load_conditions(&config.data_dir, &config.lang).map_err(|e| format!("Error loading cached conditions: {}", e))?)
         }
+        None => None,
+    };
+    
+    if let Some(conditions) = conditions.filter(|c| !c.is_empty()) {
+        let engine = state.get(&config).await?;
+        
+        engine
+            .embed_conditions(conditions)
+            .await
+            .map_err(|e| format!("Error embedding: {}", e))?;
     }
     
     Ok("Database initialized".to_string())
 }
 
-/// Perform a symptom search
+/// Perform a symptom search, returning the `top_k` best conditions. `top_k` is the number of
+/// results shown; how many candidates each table contributes comes from --top-k or the config
+/// file, as in the terminal.
 #[tauri::command]
-async fn search_symptoms(symptoms: String, top_k: usize) -> Result<Vec<SearchResult>, String> {
+async fn search_symptoms(
+    config: tauri::State<'_, AppConfig>,
+    state: tauri::State<'_, EngineState>,
+    symptoms: String,
+    top_k: usize,
+) -> Result<Vec<RankedConditionOut>, String> {
     if symptoms.trim().is_empty() {
         return Err("Please enter your symptoms".to_string());
     }
+    validate_query(&symptoms).map_err(|e| e.to_string())?;
     
-    if !embedding::has_embeddings().await {
+    if !embedding::has_embeddings(&config.data_dir, config.model, &config.lang).await {
         return Err("Database not initialized. Please run initialization first.".to_string());
     }
     
-    let mut model = fastembed::TextEmbedding::try_new(
-        fastembed::InitOptions::new(fastembed::EmbeddingModel::EmbeddingGemma300M),
-    ).map_err(|e| format!("Error loading model: {}", e))?;
+    let engine = state.get(&config).await?;
     
-    let query_embedding = model
-        .embed(vec![symptoms], None)
-        .map_err(|e| format!("Error embedding query: {}", e))?[0]
-        .clone();
-    
-    let results = search::cross_reference_search(query_embedding, top_k)
+    let results = engine
+        .search(&symptoms, top_k)
         .await
         .map_err(|e| format!("Search error: {}", e))?;
     
-    let search_results: Vec<SearchResult> = results
-        .into_iter()
-        .enumerate()
-        .map(|(i, r)| SearchResult {
-            rank: i + 1,
-            name: r.name,
-            score: r.score,
-            description_matches: r.description_matches,
-            etiology_matches: r.etiology_matches,
-            manifestation_matches: r.manifestation_matches,
-            description_text: r.description_text,
-            etiology_text: r.etiology_text,
-            manifestation_text: r.manifestation_text,
-        })
-        .collect();
-    
-    Ok(search_results)
+    Ok(RankedConditionOut::list(&results))
 }
 
-fn needs_fetch() -> bool {
-    let xml_path = std::path::Path::new("data/mplus_topics_latest.xml");
-    if !xml_path.exists() {
-        return true;
+fn main() {
+    let cli = match cli::Cli::parse_with_config() {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    ui::init_logging(cli.verbose);
+    
+    if cli.runs_in_terminal() {
+        if let Err(e) = tauri::async_runtime::block_on(cli::run(cli)) {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(cli::exit_code(&e));
+        }
+        return;
     }
     
-    let metadata_path = std::path::Path::new("data/conditions_metadata.json");
-    if !metadata_path.exists() {
-        return true;
+    if let Err(e) = cli.validate_language() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
     
-    if let Ok(metadata) = std::fs::metadata(&metadata_path) {
-        if let Ok(modified) = metadata.modified() {
-            let modified_time = chrono::DateTime::<chrono::Utc>::from(modified);
-            let now = chrono::Utc::now();
-            let days_since_update = (now - modified_time).num_days();
-            
-            if days_since_update >= 10 {
-                return true;
-            }
+    let weights = match cli.search_weights() {
+        Ok(weights) => weights,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
         }
-    }
+    };
+    let topic_filter = cli.topic_filter();
     
-    false
-}
-
-fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
+        .manage(AppConfig {
+            data_dir: cli.data_dir.clone(),
+            model: cli.model,
+            execution_provider: cli.execution_provider,
+            lang: cli.lang.clone(),
+            fetch_retries: cli.fetch_retries,
+            max_age_days: cli.max_age_days,
+            topic_filter,
+            weights,
+            cli,
+        })
+        .manage(EngineState::default())
         .invoke_handler(tauri::generate_handler![
             check_database,
             initialize_database,
@@ -0,0 +1,249 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::embedding::{DatabaseStats, EmbeddingType, ModelChoice, SearchResult};
+use crate::fetch::is_meaningful;
+use crate::search::{ComparedCondition, MatchKind, RankedCondition};
+
+/// Version of the JSON output, bumped whenever a field is renamed, removed or changes meaning
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Name of the embedding model as recorded in the database's version marker
+fn model_name(model: Option<ModelChoice>) -> Option<String> {
+    model.map(|m| m.embedding_model().to_string())
+}
+
+/// One ranked condition as it appears in JSON output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankedConditionOut {
+    pub rank: usize,
+    pub name: String,
+    pub score: f32,
+    /// "semantic" or "name"
+    pub match_kind: String,
+    pub groups: Vec<String>,
+    pub medline_url: String,
+    pub description_matches: usize,
+    pub etiology_matches: usize,
+    pub manifestation_matches: usize,
+    pub treatment_matches: usize,
+    pub description_score: Option<f32>,
+    pub etiology_score: Option<f32>,
+    pub manifestation_score: Option<f32>,
+    pub treatment_score: Option<f32>,
+    pub description_text: Option<String>,
+    pub etiology_text: Option<String>,
+    pub manifestation_text: Option<String>,
+    pub treatment_text: Option<String>,
+}
+
+impl RankedConditionOut {
+    /// Output for ranked conditions in order, numbered from 1, with placeholder section text left out
+    pub fn list(results: &[RankedCondition]) -> Vec<Self> {
+        let text = |t: &Option<String>| t.clone().filter(|t| is_meaningful(t));
+        results
+            .iter()
+            .enumerate()
+            .map(|(i, r)| Self {
+                rank: i + 1,
+                name: r.name.clone(),
+                score: r.score,
+                match_kind: match r.match_kind {
+                    MatchKind::Semantic => "semantic",
+                    MatchKind::Name => "name",
+                }
+                .to_string(),
+                groups: r.groups.clone(),
+                medline_url: r.medline_url.clone(),
+                description_matches: r.description_matches,
+                etiology_matches: r.etiology_matches,
+                manifestation_matches: r.manifestation_matches,
+                treatment_matches: r.treatment_matches,
+                description_score: r.description_score,
+                etiology_score: r.etiology_score,
+                manifestation_score: r.manifestation_score,
+                treatment_score: r.treatment_score,
+                description_text: text(&r.description_text),
+                etiology_text: text(&r.etiology_text),
+                manifestation_text: text(&r.manifestation_text),
+                treatment_text: text(&r.treatment_text),
+            })
+            .collect()
+    }
+}
+
+/// Results of one search
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResponse {
+    pub schema_version: u32,
+    pub query: String,
+    /// Embedding model the results came from, unknown for results saved earlier
+    pub model: Option<String>,
+    pub generated_at: DateTime<Utc>,
+    pub results: Vec<RankedConditionOut>,
+}
+
+impl SearchResponse {
+    pub fn new(query: &str, model: Option<ModelChoice>, results: &[RankedCondition]) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            query: query.to_string(),
+            model: model_name(model),
+            generated_at: Utc::now(),
+            results: RankedConditionOut::list(results),
+        }
+    }
+}
+
+/// One line of `--queries-file` output: a search response, with `error` set and no results when the search failed
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchLine {
+    pub line: usize,
+    #[serde(flatten)]
+    pub response: SearchResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One raw match from a single section's table
+#[derive(Debug, Clone, Serialize)]
+pub struct TableMatchOut {
+    pub rank: usize,
+    pub name: String,
+    pub score: f32,
+    pub text: String,
+    pub groups: Vec<String>,
+    pub medline_url: String,
+}
+
+/// Raw matches for a query from one section's table
+#[derive(Debug, Clone, Serialize)]
+pub struct TableResponse {
+    pub schema_version: u32,
+    pub query: String,
+    pub table: EmbeddingType,
+    pub model: Option<String>,
+    pub generated_at: DateTime<Utc>,
+    pub results: Vec<TableMatchOut>,
+}
+
+impl TableResponse {
+    pub fn new(query: &str, table: EmbeddingType, model: Option<ModelChoice>, results: &[SearchResult]) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            query: query.to_string(),
+            table,
+            model: model_name(model),
+            generated_at: Utc::now(),
+            results: results
+                .iter()
+                .enumerate()
+                .map(|(i, r)| TableMatchOut {
+                    rank: i + 1,
+                    name: r.condition_name.clone(),
+                    score: r.score,
+                    text: r.text.clone(),
+                    groups: r.groups.clone(),
+                    medline_url: r.medline_url.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A query and its ranked conditions within a comparison
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryResults {
+    pub query: String,
+    pub results: Vec<RankedConditionOut>,
+}
+
+/// Two searches and how their rankings differ
+#[derive(Debug, Clone, Serialize)]
+pub struct ComparisonResponse {
+    pub schema_version: u32,
+    pub model: Option<String>,
+    pub generated_at: DateTime<Utc>,
+    pub first: QueryResults,
+    pub second: QueryResults,
+    pub changes: Vec<ComparedCondition>,
+}
+
+impl ComparisonResponse {
+    pub fn new(
+        model: Option<ModelChoice>,
+        first: (&str, &[RankedCondition]),
+        second: (&str, &[RankedCondition]),
+        changes: Vec<ComparedCondition>,
+    ) -> Self {
+        let query_results = |(query, results): (&str, &[RankedCondition])| QueryResults {
+            query: query.to_string(),
+            results: RankedConditionOut::list(results),
+        };
+        Self {
+            schema_version: SCHEMA_VERSION,
+            model: model_name(model),
+            generated_at: Utc::now(),
+            first: query_results(first),
+            second: query_results(second),
+            changes,
+        }
+    }
+}
+
+/// Contents of one embedding table
+#[derive(Debug, Clone, Serialize)]
+pub struct TableStatsOut {
+    pub name: String,
+    pub exists: bool,
+    pub rows: usize,
+    pub dimension: Option<usize>,
+    pub oldest: Option<DateTime<Utc>>,
+    pub newest: Option<DateTime<Utc>>,
+}
+
+/// What the embedding database holds, for `--stats --format json`
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsResponse {
+    pub schema_version: u32,
+    pub generated_at: DateTime<Utc>,
+    pub path: PathBuf,
+    /// Model, vector size and language the database was built with, missing without a version marker
+    pub model: Option<String>,
+    pub dimension: Option<usize>,
+    pub language: Option<String>,
+    pub storage_version: Option<u32>,
+    pub distinct_conditions: usize,
+    pub size_bytes: u64,
+    pub tables: Vec<TableStatsOut>,
+}
+
+impl StatsResponse {
+    pub fn new(stats: &DatabaseStats) -> Self {
+        let marker = stats.marker.as_ref();
+        Self {
+            schema_version: SCHEMA_VERSION,
+            generated_at: Utc::now(),
+            path: stats.path.clone(),
+            model: marker.map(|m| m.model.clone()),
+            dimension: marker.map(|m| m.dimension),
+            language: marker.map(|m| m.language.clone()),
+            storage_version: marker.map(|m| m.storage_version),
+            distinct_conditions: stats.distinct_conditions,
+            size_bytes: stats.size_bytes,
+            tables: stats
+                .tables
+                .iter()
+                .map(|t| TableStatsOut {
+                    name: t.name.clone(),
+                    exists: t.exists,
+                    rows: t.rows,
+                    dimension: t.dimension,
+                    oldest: t.oldest,
+                    newest: t.newest,
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::search::RankedCondition;
+
+/// Ranked conditions kept under a name so they can be shown again later
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedResults {
+    pub name: String,
+    pub query: String,
+    pub saved_at: String,
+    pub results: Vec<RankedCondition>,
+}
+
+/// Directory inside the data directory that holds saved result sets
+pub fn saved_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("saved_results")
+}
+
+/// Filesystem-safe form of a name: letters, digits, '-' and '_' are kept and anything else becomes '_'
+pub fn sanitize_name(name: &str) -> Result<String> {
+    let sanitized: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let sanitized = sanitized.trim_matches('_').to_string();
+    if sanitized.is_empty() {
+        bail!("'{}' can't be used as a name; use letters, digits, '-' or '_'", name);
+    }
+    Ok(sanitized)
+}
+
+/// File a result set with this name is saved to
+pub fn saved_path(data_dir: &Path, name: &str) -> Result<PathBuf> {
+    Ok(saved_dir(data_dir).join(format!("{}.json", sanitize_name(name)?)))
+}
+
+/// Whether a result set with this name has already been saved
+pub fn exists(data_dir: &Path, name: &str) -> Result<bool> {
+    Ok(saved_path(data_dir, name)?.exists())
+}
+
+/// Save results under a name, replacing any set saved with the same name, and return the file written
+pub fn save(data_dir: &Path, name: &str, query: &str, results: &[RankedCondition]) -> Result<PathBuf> {
+    let path = saved_path(data_dir, name)?;
+    let saved = SavedResults {
+        name: sanitize_name(name)?,
+        query: query.to_string(),
+        saved_at: Local::now().to_rfc3339(),
+        results: results.to_vec(),
+    };
+    
+    fs::create_dir_all(saved_dir(data_dir))?;
+    fs::write(&path, serde_json::to_string_pretty(&saved)?)?;
+    Ok(path)
+}
+
+/// Load a saved result set, listing the saved names when there is none by that name
+pub fn load(data_dir: &Path, name: &str) -> Result<SavedResults> {
+    let path = saved_path(data_dir, name)?;
+    if !path.exists() {
+        let names = list(data_dir);
+        if names.is_empty() {
+            bail!("No saved results named '{}'; nothing has been saved yet", name);
+        }
+        bail!("No saved results named '{}'; saved sets are: {}", name, names.join(", "));
+    }
+    
+    let content = fs::read_to_string(&path)?;
+    serde_json::from_str(&content).with_context(|| format!("Could not read saved results {}", path.display()))
+}
+
+/// Names of the saved result sets, sorted
+pub fn list(data_dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(saved_dir(data_dir)) else {
+        return Vec::new();
+    };
+    
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let is_json = path.extension().is_some_and(|ext| ext == "json");
+            is_json.then(|| path.file_stem()?.to_str().map(str::to_string)).flatten()
+        })
+        .collect();
+    names.sort();
+    names
+}
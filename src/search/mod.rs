@@ -1,150 +1,1230 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+use log::{debug, info, warn};
 
-use crate::embedding::{get_table, search_table};
+use crate::embedding::{check_query_embedding, get_table, list_conditions, normalize, search_table, CorruptTable, EmbeddingType, Metric, RowFilter, SearchResult, SectionPresence};
+use crate::fetch::is_meaningful;
+use crate::ui;
+
+/// How a condition was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchKind {
+    /// Its sections are semantically close to the query
+    #[default]
+    Semantic,
+    /// Its name resembles the query text
+    Name,
+}
+
+impl std::fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchKind::Semantic => write!(f, "semantic match"),
+            MatchKind::Name => write!(f, "name match"),
+        }
+    }
+}
 
 /// Final ranked condition result
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RankedCondition {
     pub name: String,
     pub score: f32,
+    pub match_kind: MatchKind,
+    pub groups: Vec<String>,
+    pub medline_url: String,
     pub description_matches: usize,
     pub etiology_matches: usize,
     pub manifestation_matches: usize,
+    pub treatment_matches: usize,
+    pub description_score: Option<f32>,
+    pub etiology_score: Option<f32>,
+    pub manifestation_score: Option<f32>,
+    pub treatment_score: Option<f32>,
     pub description_text: Option<String>,
     pub etiology_text: Option<String>,
     pub manifestation_text: Option<String>,
+    pub treatment_text: Option<String>,
+    #[serde(default)]
+    pub sections: SectionPresence,
 }
 
-/// Cross-reference search across all three embedding tables
-pub async fn cross_reference_search(
-    query_embedding: Vec<f32>,
-    top_k_per_table: usize,
-) -> Result<Vec<RankedCondition>> {
-    println!("Searching for similar conditions...");
-    
-    let description_table = get_table("description_embeddings").await?;
-    let etiology_table = get_table("etiology_embeddings").await?;
-    let manifestation_table = get_table("manifestation_embeddings").await?;
+/// Scoring weight for a description match
+pub const DESCRIPTION_WEIGHT: f32 = 1.0;
+/// Scoring weight for an etiology match
+pub const ETIOLOGY_WEIGHT: f32 = 1.2;
+/// Scoring weight for a manifestation match
+pub const MANIFESTATION_WEIGHT: f32 = 1.5;
+/// Scoring weight for a treatment match
+pub const TREATMENT_WEIGHT: f32 = 0.8;
+
+/// Per-table scoring weights
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Weights {
+    pub description: f32,
+    pub etiology: f32,
+    pub manifestation: f32,
+    pub treatment: f32,
+}
+
+impl Weights {
+    /// Highest score a condition can reach, matching every table with similarity 1.0
+    pub fn total(&self) -> f32 {
+        self.description + self.etiology + self.manifestation + self.treatment
+    }
     
-    println!("  - Searching description embeddings...");
-    let mut description_results = search_table(&description_table, query_embedding.clone(), top_k_per_table).await?;
-    for r in description_results.iter_mut() {
-        r.embedding_type = "description".to_string();
+    /// Highest score a condition with only these sections can reach
+    pub fn present_total(&self, sections: SectionPresence) -> f32 {
+        [
+            (sections.description, self.description),
+            (sections.etiology, self.etiology),
+            (sections.manifestation, self.manifestation),
+            (sections.treatment, self.treatment),
+        ]
+        .into_iter()
+        .filter(|(present, _)| *present)
+        .map(|(_, weight)| weight)
+        .sum()
     }
     
-    println!("  - Searching etiology embeddings...");
-    let mut etiology_results = search_table(&etiology_table, query_embedding.clone(), top_k_per_table).await?;
-    for r in etiology_results.iter_mut() {
-        r.embedding_type = "etiology".to_string();
+    /// Factor a condition's score is scaled by to make up for sections it has no text for,
+    /// at most `MAX_SPARSE_SCALE` so a condition with one or two sections can't outscore
+    /// conditions that matched in more tables
+    pub fn sparse_scale(&self, sections: SectionPresence) -> f32 {
+        let present_total = self.present_total(sections);
+        if present_total > 0.0 {
+            (self.total() / present_total).min(MAX_SPARSE_SCALE)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Largest `Weights::sparse_scale`, which with the default weights makes up for any one missing section
+const MAX_SPARSE_SCALE: f32 = 1.5;
+
+/// Fraction of the best possible score at or above which a match is called strong
+const STRONG_MATCH: f32 = 0.4;
+
+/// Fraction of the best possible score at or above which a match is called possible
+const POSSIBLE_MATCH: f32 = 0.2;
+
+/// How closely a condition's information matches the description, in words a lay reader can follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confidence {
+    Strong,
+    Possible,
+    Weak,
+}
+
+impl Confidence {
+    /// Band a weighted score by its share of the best score the weights allow
+    pub fn from_score(score: f32, weights: &Weights) -> Self {
+        let normalized = if weights.total() > 0.0 { score / weights.total() } else { 0.0 };
+        if normalized >= STRONG_MATCH {
+            Confidence::Strong
+        } else if normalized >= POSSIBLE_MATCH {
+            Confidence::Possible
+        } else {
+            Confidence::Weak
+        }
     }
+}
+
+impl std::fmt::Display for Confidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Confidence::Strong => write!(f, "strong match"),
+            Confidence::Possible => write!(f, "possible match"),
+            Confidence::Weak => write!(f, "weak match"),
+        }
+    }
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            description: DESCRIPTION_WEIGHT,
+            etiology: ETIOLOGY_WEIGHT,
+            manifestation: MANIFESTATION_WEIGHT,
+            treatment: TREATMENT_WEIGHT,
+        }
+    }
+}
+
+impl FromStr for Weights {
+    type Err = anyhow::Error;
     
-    println!("  - Searching manifestation embeddings...");
-    let mut manifestation_results = search_table(&manifestation_table, query_embedding.clone(), top_k_per_table).await?;
-    for r in manifestation_results.iter_mut() {
-        r.embedding_type = "manifestation".to_string();
+    /// Parse `desc,etio,manif[,treat]`, keeping the default treatment weight when it is left out
+    fn from_str(s: &str) -> Result<Self> {
+        let values = s
+            .split(',')
+            .map(|v| {
+                let v = v.trim();
+                v.parse::<f32>().map_err(|_| anyhow::anyhow!("Invalid weight '{}'", v))
+            })
+            .collect::<Result<Vec<f32>>>()?;
+        
+        if values.len() != 3 && values.len() != 4 {
+            bail!("Expected weights as desc,etio,manif[,treat] but got {} values", values.len());
+        }
+        if let Some(v) = values.iter().find(|v| !v.is_finite() || **v < 0.0) {
+            bail!("Weights must be non-negative numbers, got {}", v);
+        }
+        
+        Ok(Self {
+            description: values[0],
+            etiology: values[1],
+            manifestation: values[2],
+            treatment: values.get(3).copied().unwrap_or(TREATMENT_WEIGHT),
+        })
+    }
+}
+
+/// Matched section texts for a single condition
+#[derive(Debug, Clone, Default)]
+struct SectionTexts {
+    description: Option<String>,
+    etiology: Option<String>,
+    manifestation: Option<String>,
+    treatment: Option<String>,
+}
+
+/// Curated set of condition names that results are restricted to
+#[derive(Debug, Clone, Default)]
+pub struct AllowList {
+    names: HashSet<String>,
+}
+
+impl AllowList {
+    /// Read a newline-delimited list of condition names. A missing or empty file gives `None`
+    /// with a warning, so searches fall back to every condition.
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Ignoring condition list {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        
+        let names: HashSet<String> = content.lines().map(normalize_name).filter(|n| !n.is_empty()).collect();
+        if names.is_empty() {
+            warn!("Condition list {} is empty; showing all conditions", path.display());
+            return None;
+        }
+        info!("Restricting results to the {} conditions in {}", names.len(), path.display());
+        Some(Self { names })
     }
     
-    let mut all_conditions: HashSet<String> = HashSet::new();
-    for r in &description_results {
-        all_conditions.insert(r.condition_name.clone());
+    /// Whether a condition is on the list, ignoring case and spacing
+    pub fn allows(&self, name: &str) -> bool {
+        self.names.contains(&normalize_name(name))
     }
-    for r in &etiology_results {
-        all_conditions.insert(r.condition_name.clone());
+}
+
+/// Lowercased name with runs of whitespace collapsed to single spaces
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// How many candidates to consider and which conditions to keep in a cross-reference search
+#[derive(Debug, Clone, Copy)]
+pub struct SearchOptions<'a> {
+    pub top_k_per_table: usize,
+    pub num_results: usize,
+    pub min_score: f32,
+    pub group: Option<&'a str>,
+    pub allowed: Option<&'a AllowList>,
+    pub weights: &'a Weights,
+    pub metric: Metric,
+    /// Ignore rows embedded before this time
+    pub created_after: Option<DateTime<Utc>>,
+}
+
+impl SearchOptions<'_> {
+    /// Rows each table search may return, so LanceDB leaves out conditions off the allow list
+    /// or outside the group before picking the nearest ones
+    pub fn row_filter(&self) -> RowFilter {
+        RowFilter {
+            created_after: self.created_after,
+            names: self.allowed.map(|allowed| allowed.names.iter().cloned().collect()),
+            group: self.group.map(str::to_string),
+        }
     }
-    for r in &manifestation_results {
-        all_conditions.insert(r.condition_name.clone());
+}
+
+/// Called with each table's matches as soon as that table has been searched
+pub type OnTable<'a> = &'a (dyn Fn(&str, &[SearchResult]) + Sync);
+
+/// Cross-reference search across all four embedding tables
+pub async fn cross_reference_search(
+    db: &lancedb::Connection,
+    mut query_embedding: Vec<f32>,
+    options: &SearchOptions<'_>,
+    on_table: OnTable<'_>,
+) -> Result<Vec<RankedCondition>> {
+    info!("Searching for similar conditions...");
+    
+    check_query_embedding(&query_embedding)?;
+    // Stored vectors are unit length, so the query is too unless the metric wants its raw length
+    if options.metric.normalizes_query() {
+        normalize(&mut query_embedding);
+    }
+    
+    // The tables are independent, so query them all at once and report each as it finishes.
+    // A table that can't be opened is left out so the others still rank conditions.
+    debug!("Searching description, etiology, manifestation and treatment embeddings...");
+    // A table that is on disk but won't open is corrupt rather than missing
+    let stored = db.table_names().execute().await.map_err(|e| CorruptTable::new("lancedb", e))?;
+    let row_filter = options.row_filter();
+    let search = |embedding_type: EmbeddingType| {
+        let query_embedding = query_embedding.clone();
+        let stored = &stored;
+        let row_filter = &row_filter;
+        async move {
+            let table_name = embedding_type.table_name();
+            let table = match get_table(db, &table_name).await {
+                Ok(table) => table,
+                Err(e) if stored.contains(&table_name) => return Err(CorruptTable::new(&table_name, e).into()),
+                Err(e) => {
+                    warn!("Skipping {} embeddings: {}", embedding_type, e);
+                    return Ok(None);
+                }
+            };
+            let results = search_table(&table, embedding_type, query_embedding, options.top_k_per_table, row_filter, options.metric).await?;
+            on_table(embedding_type.as_str(), &results);
+            Ok::<_, anyhow::Error>(Some(results))
+        }
+    };
+    let tables = tokio::try_join!(
+        search(EmbeddingType::Description),
+        search(EmbeddingType::Etiology),
+        search(EmbeddingType::Manifestation),
+        search(EmbeddingType::Treatment),
+    )?;
+    if tables.0.is_none() && tables.1.is_none() && tables.2.is_none() && tables.3.is_none() {
+        bail!("No embedding tables found; run without --no-update to build them");
     }
+    let (description_results, etiology_results, manifestation_results, treatment_results) = (
+        tables.0.unwrap_or_default(),
+        tables.1.unwrap_or_default(),
+        tables.2.unwrap_or_default(),
+        tables.3.unwrap_or_default(),
+    );
     
-    println!("  - Cross-referencing {} conditions...", all_conditions.len());
+    let top_results = rank(
+        description_results,
+        etiology_results,
+        manifestation_results,
+        treatment_results,
+        options.weights,
+        options.min_score,
+        options.num_results,
+    );
     
-    let mut condition_texts: HashMap<String, (Option<String>, Option<String>, Option<String>)> = HashMap::new();
+    info!("Found top {} conditions", top_results.len());
+    
+    Ok(top_results)
+}
+
+/// Merge each table's matches into conditions ranked by their weighted similarity, best first
+pub fn rank(
+    description_results: Vec<SearchResult>,
+    etiology_results: Vec<SearchResult>,
+    manifestation_results: Vec<SearchResult>,
+    treatment_results: Vec<SearchResult>,
+    weights: &Weights,
+    min_score: f32,
+    num_results: usize,
+) -> Vec<RankedCondition> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    let all_conditions: Vec<String> = description_results
+        .iter()
+        .chain(&etiology_results)
+        .chain(&manifestation_results)
+        .chain(&treatment_results)
+        .filter(|r| seen.insert(r.condition_name.as_str()))
+        .map(|r| r.condition_name.clone())
+        .collect();
+    
+    debug!("Cross-referencing {} conditions...", all_conditions.len());
+    
+    let mut condition_texts: HashMap<String, SectionTexts> = HashMap::new();
+    let mut condition_groups: HashMap<String, Vec<String>> = HashMap::new();
+    let mut condition_urls: HashMap<String, String> = HashMap::new();
+    let mut condition_sections: HashMap<String, SectionPresence> = HashMap::new();
     
     for r in &description_results {
-        let entry = condition_texts.entry(r.condition_name.clone()).or_insert((None, None, None));
-        entry.0 = Some(r.text.clone());
+        let entry = condition_texts.entry(r.condition_name.clone()).or_default();
+        entry.description = Some(r.text.clone());
     }
     for r in &etiology_results {
-        let entry = condition_texts.entry(r.condition_name.clone()).or_insert((None, None, None));
-        entry.1 = Some(r.text.clone());
+        let entry = condition_texts.entry(r.condition_name.clone()).or_default();
+        entry.etiology = Some(r.text.clone());
     }
     for r in &manifestation_results {
-        let entry = condition_texts.entry(r.condition_name.clone()).or_insert((None, None, None));
-        entry.2 = Some(r.text.clone());
+        let entry = condition_texts.entry(r.condition_name.clone()).or_default();
+        entry.manifestation = Some(r.text.clone());
+    }
+    for r in &treatment_results {
+        let entry = condition_texts.entry(r.condition_name.clone()).or_default();
+        entry.treatment = Some(r.text.clone());
+    }
+    for r in description_results.iter().chain(&etiology_results).chain(&manifestation_results).chain(&treatment_results) {
+        condition_groups.entry(r.condition_name.clone()).or_insert_with(|| r.groups.clone());
+        condition_urls.entry(r.condition_name.clone()).or_insert_with(|| r.medline_url.clone());
+        condition_sections.entry(r.condition_name.clone()).or_insert(r.sections);
     }
     
-    let description_set: HashSet<String> = description_results.iter().map(|r| r.condition_name.clone()).collect();
-    let etiology_set: HashSet<String> = etiology_results.iter().map(|r| r.condition_name.clone()).collect();
-    let manifestation_set: HashSet<String> = manifestation_results.iter().map(|r| r.condition_name.clone()).collect();
+    let description_scores: HashMap<String, f32> = description_results.iter().map(|r| (r.condition_name.clone(), r.score)).collect();
+    let etiology_scores: HashMap<String, f32> = etiology_results.iter().map(|r| (r.condition_name.clone(), r.score)).collect();
+    let manifestation_scores: HashMap<String, f32> = manifestation_results.iter().map(|r| (r.condition_name.clone(), r.score)).collect();
+    let treatment_scores: HashMap<String, f32> = treatment_results.iter().map(|r| (r.condition_name.clone(), r.score)).collect();
     
     let mut ranked_conditions: Vec<RankedCondition> = Vec::new();
     
     for condition_name in all_conditions {
-        let desc_match = if description_set.contains(&condition_name) { 1 } else { 0 };
-        let etio_match = if etiology_set.contains(&condition_name) { 1 } else { 0 };
-        let manif_match = if manifestation_set.contains(&condition_name) { 1 } else { 0 };
+        let desc_score = description_scores.get(&condition_name).copied();
+        let etio_score = etiology_scores.get(&condition_name).copied();
+        let manif_score = manifestation_scores.get(&condition_name).copied();
+        let treat_score = treatment_scores.get(&condition_name).copied();
         
-        let score = (desc_match as f32 * 1.0) + (etio_match as f32 * 1.2) + (manif_match as f32 * 1.5);
+        let desc_match = if desc_score.is_some() { 1 } else { 0 };
+        let etio_match = if etio_score.is_some() { 1 } else { 0 };
+        let manif_match = if manif_score.is_some() { 1 } else { 0 };
+        let treat_match = if treat_score.is_some() { 1 } else { 0 };
         
-        let texts = condition_texts.get(&condition_name).cloned().unwrap_or((None, None, None));
+        // Sum the weighted similarities of every table the condition matched in
+        let mut score = (desc_score.unwrap_or(0.0) * weights.description)
+            + (etio_score.unwrap_or(0.0) * weights.etiology)
+            + (manif_score.unwrap_or(0.0) * weights.manifestation)
+            + (treat_score.unwrap_or(0.0) * weights.treatment);
+        // Sections with no text can't match, so scale up toward what the condition could have scored with all of them
+        let sections = condition_sections.get(&condition_name).copied().unwrap_or_default();
+        score *= weights.sparse_scale(sections);
+        
+        let texts = condition_texts.get(&condition_name).cloned().unwrap_or_default();
+        let groups = condition_groups.remove(&condition_name).unwrap_or_default();
+        let medline_url = condition_urls.remove(&condition_name).unwrap_or_default();
         
         ranked_conditions.push(RankedCondition {
             name: condition_name,
             score,
+            match_kind: MatchKind::Semantic,
+            groups,
+            medline_url,
             description_matches: desc_match,
             etiology_matches: etio_match,
             manifestation_matches: manif_match,
-            description_text: texts.0,
-            etiology_text: texts.1,
-            manifestation_text: texts.2,
+            treatment_matches: treat_match,
+            description_score: desc_score,
+            etiology_score: etio_score,
+            manifestation_score: manif_score,
+            treatment_score: treat_score,
+            description_text: texts.description,
+            etiology_text: texts.etiology,
+            manifestation_text: texts.manifestation,
+            treatment_text: texts.treatment,
+            sections,
         });
     }
     
-    ranked_conditions.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    // A NaN score fails the comparison, so it is dropped here rather than sorted
+    ranked_conditions.retain(|c| c.score >= min_score);
+    ranked_conditions.sort_by(by_score_then_name);
+    ranked_conditions.truncate(num_results);
+    ranked_conditions
+}
+
+/// Highest score first, with equal scores in name order so ties rank the same way every run
+fn by_score_then_name(a: &RankedCondition, b: &RankedCondition) -> std::cmp::Ordering {
+    b.score.total_cmp(&a.score).then_with(|| a.name.cmp(&b.name))
+}
+
+/// Lowest name similarity (0 to 1) shown by the name fallback
+const NAME_MATCH_THRESHOLD: f64 = 0.8;
+
+/// Find conditions whose names resemble the query, for when the semantic search finds nothing
+pub async fn name_match_search(
+    db: &lancedb::Connection,
+    query: &str,
+    options: &SearchOptions<'_>,
+) -> Result<Vec<RankedCondition>> {
+    let query = query.trim().to_lowercase();
+    let group = options.group.map(str::to_lowercase);
+    
+    let mut matches: Vec<RankedCondition> = list_conditions(db)
+        .await?
+        .into_iter()
+        .filter(|c| match &group {
+            Some(group) => c.groups.iter().any(|g| g.to_lowercase().contains(group)),
+            None => true,
+        })
+        .filter(|c| options.allowed.is_none_or(|allowed| allowed.allows(&c.condition_name)))
+        .filter_map(|c| {
+            let name = c.condition_name.to_lowercase();
+            // A query that appears inside the name ("lupus" in "Lupus Nephritis") counts as a full match
+            let similarity = if name.contains(&query) { 1.0 } else { strsim::jaro_winkler(&query, &name) };
+            if similarity < NAME_MATCH_THRESHOLD {
+                return None;
+            }
+            
+            let mut texts = SectionTexts::default();
+            match c.embedding_type {
+                EmbeddingType::Description => texts.description = Some(c.text),
+                EmbeddingType::Etiology => texts.etiology = Some(c.text),
+                EmbeddingType::Manifestation => texts.manifestation = Some(c.text),
+                EmbeddingType::Treatment => texts.treatment = Some(c.text),
+            }
+            
+            Some(RankedCondition {
+                name: c.condition_name,
+                score: similarity as f32,
+                match_kind: MatchKind::Name,
+                groups: c.groups,
+                medline_url: c.medline_url,
+                description_matches: 0,
+                etiology_matches: 0,
+                manifestation_matches: 0,
+                treatment_matches: 0,
+                description_score: None,
+                etiology_score: None,
+                manifestation_score: None,
+                treatment_score: None,
+                description_text: texts.description,
+                etiology_text: texts.etiology,
+                manifestation_text: texts.manifestation,
+                treatment_text: texts.treatment,
+                sections: c.sections,
+            })
+        })
+        .collect();
     
-    let top_5: Vec<RankedCondition> = ranked_conditions.into_iter().take(5).collect();
+    matches.sort_by(by_score_then_name);
+    matches.truncate(options.num_results);
+    Ok(matches)
+}
+
+/// First-person filler that dilutes a query, removed as whole phrases
+const QUERY_FILLER: &[&str] = &[
+    "i have been", "i've been", "i have got", "i've got", "i have", "i've", "i am", "i'm",
+    "i feel", "i keep", "i get", "i think", "feeling", "feel", "kind of", "sort of",
+    "a bit", "a lot of", "really", "very", "pretty", "lately", "recently", "my", "me",
+];
+
+/// Everyday wording rewritten into the terms MedlinePlus uses, bundled with the binary
+const DEFAULT_SYNONYMS: &str = include_str!("synonyms.txt");
+
+/// Medical abbreviations rewritten into the condition names they stand for, bundled with the binary
+const DEFAULT_ALIASES: &str = include_str!("aliases.txt");
+
+/// Phrases in a query rewritten into clinical terms or full condition names before embedding
+#[derive(Debug, Clone)]
+pub struct SynonymMap {
+    /// Lowercased phrase and replacement pairs, longest phrase first so "throwing up" wins over "up"
+    pairs: Vec<(String, String)>,
+}
+
+impl Default for SynonymMap {
+    fn default() -> Self {
+        Self::parse(DEFAULT_SYNONYMS)
+    }
+}
+
+impl SynonymMap {
+    /// Bundled abbreviations such as "uti" and "copd" mapped to full condition names
+    pub fn aliases() -> Self {
+        Self::parse(DEFAULT_ALIASES)
+    }
     
-    println!("Found top {} conditions", top_5.len());
+    /// Read a map of `phrase = replacement` lines, `#` starting a comment. A missing or empty
+    /// file gives `None` with a warning, so the caller can fall back to a bundled map.
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Ignoring {}: {}; using the bundled list", path.display(), e);
+                return None;
+            }
+        };
+        
+        let map = Self::parse(&content);
+        if map.pairs.is_empty() {
+            warn!("{} has no `phrase = replacement` lines; using the bundled list", path.display());
+            return None;
+        }
+        info!("Loaded {} replacements from {}", map.pairs.len(), path.display());
+        Some(map)
+    }
     
-    Ok(top_5)
+    fn parse(content: &str) -> Self {
+        let mut pairs: Vec<(String, String)> = content
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .filter_map(|line| line.split_once('='))
+            .map(|(phrase, replacement)| (words_only(phrase), clean_query(replacement)))
+            .filter(|(phrase, replacement)| !phrase.is_empty() && !replacement.is_empty())
+            .collect();
+        pairs.sort_by_key(|(phrase, _)| std::cmp::Reverse(phrase.len()));
+        Self { pairs }
+    }
+    
+    /// Longest phrase whose words start at `tokens[0]`
+    fn match_at(&self, tokens: &[String]) -> Option<&(String, String)> {
+        self.pairs.iter().find(|(phrase, _)| starts_with_words(tokens, phrase))
+    }
+    
+    /// Rewrite every whole-word phrase in the query, returning it lowercased with punctuation other than commas dropped
+    pub fn expand(&self, query: &str) -> String {
+        let tokens = tokenize(query);
+        let mut expanded = Vec::with_capacity(tokens.len());
+        let mut i = 0;
+        while i < tokens.len() {
+            match self.match_at(&tokens[i..]) {
+                Some((phrase, replacement)) => {
+                    expanded.extend(tokenize(replacement));
+                    i += phrase.split(' ').count();
+                }
+                None => {
+                    expanded.push(tokens[i].clone());
+                    i += 1;
+                }
+            }
+        }
+        join_tokens(&expanded)
+    }
+    
+    /// Phrases in the query that `expand` would rewrite, with what each becomes, in query order
+    pub fn matches(&self, query: &str) -> Vec<(&str, &str)> {
+        let tokens = tokenize(query);
+        let mut matches: Vec<(&str, &str)> = Vec::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            match self.match_at(&tokens[i..]) {
+                Some((phrase, replacement)) => {
+                    if !matches.iter().any(|(p, _)| p == phrase) {
+                        matches.push((phrase, replacement));
+                    }
+                    i += phrase.split(' ').count();
+                }
+                None => i += 1,
+            }
+        }
+        matches
+    }
+}
+
+/// Lowercased words of a query, keeping letters, digits and apostrophes, with each comma as a token of its own
+fn tokenize(query: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in query.to_lowercase().chars() {
+        if c.is_alphanumeric() || c == '\'' {
+            word.push(c);
+            continue;
+        }
+        if !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+        if c == ',' {
+            tokens.push(",".to_string());
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
 }
 
-/// Display search results to user
-pub fn display_results(results: &[RankedCondition]) {
+/// Tokens back into text with single spaces between words and each comma after the word before it
+fn join_tokens(tokens: &[String]) -> String {
+    let mut text = String::new();
+    for token in tokens {
+        if !text.is_empty() && token != "," {
+            text.push(' ');
+        }
+        text.push_str(token);
+    }
+    text
+}
+
+/// Whether `tokens` begins with every word of the space-separated `phrase`
+fn starts_with_words(tokens: &[String], phrase: &str) -> bool {
+    let words: Vec<&str> = phrase.split(' ').collect();
+    tokens.len() >= words.len() && tokens.iter().zip(&words).all(|(token, word)| token == word)
+}
+
+/// Lowercase a query, keeping only letters, digits, apostrophes and commas, with single spaces between words
+fn clean_query(query: &str) -> String {
+    join_tokens(&tokenize(query))
+}
+
+/// Lowercased words of a phrase with all punctuation dropped, as matched against query tokens
+fn words_only(phrase: &str) -> String {
+    tokenize(phrase).into_iter().filter(|t| t != ",").collect::<Vec<_>>().join(" ")
+}
+
+/// Strip first-person filler and normalize everyday symptom wording before embedding
+pub fn preprocess_query(query: &str, synonyms: &SynonymMap) -> String {
+    let tokens = tokenize(&synonyms.expand(query));
+    let mut kept = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        match QUERY_FILLER.iter().find(|phrase| starts_with_words(&tokens[i..], phrase)) {
+            Some(phrase) => i += phrase.split(' ').count(),
+            None => {
+                kept.push(tokens[i].clone());
+                i += 1;
+            }
+        }
+    }
+    
+    let result = join_tokens(&kept);
+    if result.is_empty() {
+        query.trim().to_string()
+    } else {
+        result
+    }
+}
+
+/// Truncate a string to at most `max` characters without splitting a UTF-8 code point
+pub fn truncate_chars(s: &str, max: usize) -> &str {
+    match s.char_indices().nth(max) {
+        Some((idx, _)) => &s[..idx],
+        None => s,
+    }
+}
+
+/// Common words left unhighlighted in snippets even when the query contains them
+const STOP_WORDS: &[&str] = &[
+    "the", "and", "with", "have", "has", "had", "for", "from", "that", "this", "are", "was", "not",
+    "but", "can", "been", "feel", "feeling", "some", "very", "your", "you",
+];
+
+/// Crude stem of a lowercased word, so "coughing", "coughs" and "cough" compare equal
+fn stem(word: &str) -> &str {
+    for suffix in ["ness", "ing", "ed", "es", "ly", "s"] {
+        if let Some(stem) = word.strip_suffix(suffix) {
+            if stem.chars().count() >= 3 {
+                return stem;
+            }
+        }
+    }
+    word
+}
+
+/// Stems of the words in a query worth highlighting
+fn query_stems(query: &str) -> HashSet<String> {
+    query
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() >= 3 && !STOP_WORDS.contains(w))
+        .map(|w| stem(w).to_string())
+        .collect()
+}
+
+/// Snippet with the words whose stem appears in the query emphasized
+fn highlight_terms(snippet: &str, stems: &HashSet<String>) -> String {
+    if stems.is_empty() {
+        return snippet.to_string();
+    }
+    
+    let mut highlighted = String::with_capacity(snippet.len());
+    let mut word_start = None;
+    for (i, c) in snippet.char_indices().chain(std::iter::once((snippet.len(), ' '))) {
+        match (c.is_alphanumeric(), word_start) {
+            (true, None) => word_start = Some(i),
+            (false, Some(start)) => {
+                let word = &snippet[start..i];
+                if stems.contains(stem(&word.to_lowercase())) {
+                    highlighted.push_str(&ui::highlight(word));
+                } else {
+                    highlighted.push_str(word);
+                }
+                word_start = None;
+            }
+            _ => {}
+        }
+        if word_start.is_none() && i < snippet.len() {
+            highlighted.push(c);
+        }
+    }
+    highlighted
+}
+
+/// Display search results to user, with each score and its breakdown when explaining.
+/// Words from `query` are emphasized in the snippets.
+pub fn display_results(results: &[RankedCondition], query: &str, weights: &Weights, explain: bool, page_size: Option<usize>) {
+    if results.is_empty() {
+        println!("\nNo sufficiently relevant conditions found. Try describing your symptoms differently.\n");
+        return;
+    }
+    
     println!("\n═══════════════════════════════════════════════════════════════");
-    println!("                    TOP 5 LIKELY CONDITIONS");
+    println!("{:^63}", format!("TOP {} LIKELY CONDITIONS", results.len()));
     println!("═══════════════════════════════════════════════════════════════\n");
     
+    if results.iter().all(|c| c.match_kind == MatchKind::Name) {
+        println!("No conditions matched your description, so these are conditions whose names resemble it.\n");
+    }
+    
+    let stems = query_stems(query);
+    let snippet = |text: &str| highlight_terms(truncate_chars(text, 100), &stems);
+    let mut shown = results.len();
     for (i, condition) in results.iter().enumerate() {
+        // Pause after each full page so long result lists don't scroll off the terminal
+        if page_size.is_some_and(|size| i > 0 && i % size == 0) && !ui::confirm("More?") {
+            shown = i;
+            break;
+        }
         println!("┌─────────────────────────────────────────────────────────────┐");
-        println!("│ #{}. {} ", i + 1, condition.name);
-        println!("│    Score: {:.2}", condition.score);
-        println!("│    Matches: {} desc, {} etiology, {} manifestations",
+        println!("│ #{}. {} ", i + 1, ui::condition_name(&condition.name));
+        // Name matches are scored by spelling, which the weight bands don't describe
+        let label = match condition.match_kind {
+            MatchKind::Semantic => {
+                let confidence = Confidence::from_score(condition.score, weights);
+                ui::confidence(confidence.to_string(), confidence)
+            }
+            MatchKind::Name => ui::confidence(condition.match_kind.to_string(), Confidence::Weak),
+        };
+        if explain {
+            println!("│    {} (score {})", label, ui::score(condition.score));
+        } else {
+            println!("│    {}", label);
+        }
+        if !condition.groups.is_empty() {
+            println!("│    Groups: {}", condition.groups.join(", "));
+        }
+        println!("│    Matches: {} desc, {} etiology, {} manifestations, {} treatments",
             condition.description_matches,
             condition.etiology_matches,
-            condition.manifestation_matches
+            condition.manifestation_matches,
+            condition.treatment_matches
         );
         println!("└─────────────────────────────────────────────────────────────┘");
         
-        if let Some(ref desc) = condition.description_text {
-            if !desc.is_empty() {
-                println!("   Description: {}...", &desc[..desc.len().min(100)]);
-            }
+        if let Some(desc) = condition.description_text.as_deref().filter(|t| is_meaningful(t)) {
+            println!("   Description: {}...", snippet(desc));
         }
-        if let Some(ref etio) = condition.etiology_text {
-            if !etio.is_empty() {
-                println!("   Etiology: {}...", &etio[..etio.len().min(100)]);
-            }
+        if let Some(etio) = condition.etiology_text.as_deref().filter(|t| is_meaningful(t)) {
+            println!("   Etiology: {}...", snippet(etio));
         }
-        if let Some(ref manif) = condition.manifestation_text {
-            if !manif.is_empty() {
-                println!("   Manifestations: {}...", &manif[..manif.len().min(100)]);
-            }
+        if let Some(manif) = condition.manifestation_text.as_deref().filter(|t| is_meaningful(t)) {
+            println!("   Manifestations: {}...", snippet(manif));
+        }
+        if let Some(treat) = condition.treatment_text.as_deref().filter(|t| is_meaningful(t)) {
+            println!("   Treatments: {}...", snippet(treat));
+        }
+        if !condition.medline_url.is_empty() {
+            println!("   More info: {}", condition.medline_url);
         }
         println!();
     }
     
+    if explain {
+        display_explanation(&results[..shown], weights);
+    }
+    
+    println!("═══════════════════════════════════════════════════════════════");
+    println!("    {}", ui::disclaimer("This is NOT a diagnosis. Consult a medical professional."));
+    println!("═══════════════════════════════════════════════════════════════\n");
+}
+
+/// Show how each table's similarity contributed to a condition's score
+fn display_explanation(results: &[RankedCondition], weights: &Weights) {
     println!("═══════════════════════════════════════════════════════════════");
-    println!("    This is NOT a diagnosis. Consult a medical professional.");
+    println!("{:^63}", "SCORE BREAKDOWN");
     println!("═══════════════════════════════════════════════════════════════\n");
+    
+    for (i, condition) in results.iter().enumerate() {
+        println!("#{}. {}", i + 1, ui::condition_name(&condition.name));
+        let parts = [
+            (EmbeddingType::Description, condition.description_score, weights.description),
+            (EmbeddingType::Etiology, condition.etiology_score, weights.etiology),
+            (EmbeddingType::Manifestation, condition.manifestation_score, weights.manifestation),
+            (EmbeddingType::Treatment, condition.treatment_score, weights.treatment),
+        ];
+        for (section, similarity, weight) in parts {
+            let table = section.as_str();
+            match similarity {
+                Some(similarity) => println!(
+                    "   {:<14} {:.3} x {:.1} = {:.3}",
+                    table,
+                    similarity,
+                    weight,
+                    similarity * weight
+                ),
+                None if !condition.sections.has(section) => println!("   {:<14} no text", table),
+                None => println!("   {:<14} no match", table),
+            }
+        }
+        let scale = weights.sparse_scale(condition.sections);
+        if scale > 1.0 {
+            println!("   {:<14} x {:.2} for sections without text", "scaled", scale);
+        }
+        println!("   {:<14} {:.3}\n", "total", condition.score);
+    }
+}
+
+/// How a condition's rank moved from the first search to the second
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RankChange {
+    Rose,
+    Fell,
+    Same,
+    Entered,
+    Dropped,
+}
+
+impl std::fmt::Display for RankChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RankChange::Rose => write!(f, "rose"),
+            RankChange::Fell => write!(f, "fell"),
+            RankChange::Same => write!(f, "same"),
+            RankChange::Entered => write!(f, "entered"),
+            RankChange::Dropped => write!(f, "dropped"),
+        }
+    }
+}
+
+/// A condition's 1-based rank and score in each of two searches
+#[derive(Debug, Clone, Serialize)]
+pub struct ComparedCondition {
+    pub name: String,
+    pub first_rank: Option<usize>,
+    pub second_rank: Option<usize>,
+    pub first_score: Option<f32>,
+    pub second_score: Option<f32>,
+    pub change: RankChange,
+}
+
+/// Line up two result sets by condition, in the second search's order followed by the conditions that dropped out
+pub fn compare_results(first: &[RankedCondition], second: &[RankedCondition]) -> Vec<ComparedCondition> {
+    let first_ranks: HashMap<&str, (usize, f32)> = first
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (c.name.as_str(), (i + 1, c.score)))
+        .collect();
+    let second_names: HashSet<&str> = second.iter().map(|c| c.name.as_str()).collect();
+    
+    let mut compared: Vec<ComparedCondition> = second
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let rank = i + 1;
+            let before = first_ranks.get(c.name.as_str()).copied();
+            let change = match before {
+                None => RankChange::Entered,
+                Some((first_rank, _)) if rank < first_rank => RankChange::Rose,
+                Some((first_rank, _)) if rank > first_rank => RankChange::Fell,
+                Some(_) => RankChange::Same,
+            };
+            ComparedCondition {
+                name: c.name.clone(),
+                first_rank: before.map(|(r, _)| r),
+                second_rank: Some(rank),
+                first_score: before.map(|(_, s)| s),
+                second_score: Some(c.score),
+                change,
+            }
+        })
+        .collect();
+    
+    for (i, c) in first.iter().enumerate() {
+        if !second_names.contains(c.name.as_str()) {
+            compared.push(ComparedCondition {
+                name: c.name.clone(),
+                first_rank: Some(i + 1),
+                second_rank: None,
+                first_score: Some(c.score),
+                second_score: None,
+                change: RankChange::Dropped,
+            });
+        }
+    }
+    
+    compared
+}
+
+/// Display one table's raw matches with their cosine similarity
+pub fn display_table_results(table: &str, results: &[SearchResult]) {
+    if results.is_empty() {
+        println!("\nNo rows in the {} table.\n", table);
+        return;
+    }
+    
+    println!("\n═══════════════════════════════════════════════════════════════");
+    println!("{:^63}", format!("TOP {} {} MATCHES", results.len(), table.to_uppercase()));
+    println!("═══════════════════════════════════════════════════════════════\n");
+    
+    for (i, result) in results.iter().enumerate() {
+        println!("{:>3}. {:.4}  {}", i + 1, result.score, ui::condition_name(&result.condition_name));
+        println!("     {}...", truncate_chars(&result.text, 100));
+    }
+    println!();
+}
+
+/// Display how the ranking changed between two searches
+pub fn display_comparison(first_query: &str, second_query: &str, compared: &[ComparedCondition]) {
+    println!("\n═══════════════════════════════════════════════════════════════");
+    println!("{:^63}", "COMPARISON");
+    println!("═══════════════════════════════════════════════════════════════");
+    println!("  A: {}", first_query);
+    println!("  B: {}\n", second_query);
+    
+    if compared.is_empty() {
+        println!("Neither search found any sufficiently relevant conditions.\n");
+        return;
+    }
+    
+    let rank = |r: Option<usize>| r.map_or("-".to_string(), |r| format!("#{}", r));
+    let score = |s: Option<f32>| s.map_or("-".to_string(), |s| format!("{:.2}", s));
+    
+    println!("  {:<4} {:<4} {:<8} {:>6} {:>6}  Condition", "A", "B", "Change", "A", "B");
+    for c in compared {
+        println!(
+            "  {:<4} {:<4} {:<8} {:>6} {:>6}  {}",
+            rank(c.first_rank),
+            rank(c.second_rank),
+            c.change,
+            score(c.first_score),
+            score(c.second_score),
+            ui::condition_name(&c.name)
+        );
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Match in one table for a condition with text in every section
+    fn result(name: &str, embedding_type: EmbeddingType, score: f32) -> SearchResult {
+        SearchResult {
+            condition_name: name.to_string(),
+            text: format!("{} text for {}", embedding_type, name),
+            embedding_type,
+            sections: SectionPresence::default(),
+            groups: Vec::new(),
+            medline_url: String::new(),
+            score,
+        }
+    }
+    
+    fn names(ranked: &[RankedCondition]) -> Vec<&str> {
+        ranked.iter().map(|c| c.name.as_str()).collect()
+    }
+    
+    #[test]
+    fn match_in_three_tables_outranks_a_manifestation_only_match() {
+        let ranked = rank(
+            vec![result("Influenza", EmbeddingType::Description, 0.6)],
+            vec![result("Influenza", EmbeddingType::Etiology, 0.6)],
+            vec![result("Gout", EmbeddingType::Manifestation, 0.9), result("Influenza", EmbeddingType::Manifestation, 0.6)],
+            Vec::new(),
+            &Weights::default(),
+            0.0,
+            10,
+        );
+        
+        assert_eq!(names(&ranked), ["Influenza", "Gout"]);
+        assert_eq!((ranked[0].description_matches, ranked[0].etiology_matches, ranked[0].manifestation_matches), (1, 1, 1));
+        assert_eq!((ranked[1].description_matches, ranked[1].manifestation_matches), (0, 1));
+    }
+    
+    #[test]
+    fn equal_scores_rank_in_name_order_whatever_the_input_order() {
+        let tied = |names: [&str; 3]| {
+            let manifestation = names.iter().map(|n| result(n, EmbeddingType::Manifestation, 0.7)).collect();
+            rank(Vec::new(), Vec::new(), manifestation, Vec::new(), &Weights::default(), 0.0, 10)
+        };
+        
+        assert_eq!(names(&tied(["Gout", "Asthma", "Migraine"])), ["Asthma", "Gout", "Migraine"]);
+        assert_eq!(names(&tied(["Migraine", "Gout", "Asthma"])), ["Asthma", "Gout", "Migraine"]);
+    }
+    
+    #[test]
+    fn ties_at_the_cut_keep_the_same_conditions_every_run() {
+        // Six conditions tie for three places, with one higher and one NaN score
+        let mut manifestation: Vec<SearchResult> = ["Fever", "Cough", "Asthma", "Rash", "Gout", "Boils"]
+            .iter()
+            .map(|n| result(n, EmbeddingType::Manifestation, 0.5))
+            .collect();
+        manifestation.push(result("Zoster", EmbeddingType::Manifestation, 0.9));
+        manifestation.push(result("Broken", EmbeddingType::Manifestation, f32::NAN));
+        
+        for _ in 0..5 {
+            manifestation.rotate_left(3);
+            let ranked = rank(Vec::new(), Vec::new(), manifestation.clone(), Vec::new(), &Weights::default(), 0.0, 4);
+            assert_eq!(names(&ranked), ["Zoster", "Asthma", "Boils", "Cough"]);
+        }
+    }
+    
+    /// Match in one table for a condition with text only in the given sections
+    fn sparse_result(name: &str, embedding_type: EmbeddingType, score: f32, sections: SectionPresence) -> SearchResult {
+        SearchResult { sections, ..result(name, embedding_type, score) }
+    }
+    
+    #[test]
+    fn sparse_condition_neither_loses_nor_wins_unfairly() {
+        use EmbeddingType::{Description, Etiology, Manifestation, Treatment};
+        let weights = Weights::default();
+        let full = SectionPresence::default();
+        let no_treatment = SectionPresence { treatment: false, ..full };
+        let symptoms_only = SectionPresence { description: false, etiology: false, manifestation: true, treatment: false };
+        
+        // Matching every section it has as well as a full condition matches its own gives the same score
+        let ranked = rank(
+            vec![sparse_result("Full", Description, 0.6, full), sparse_result("Sparse", Description, 0.6, no_treatment)],
+            vec![sparse_result("Full", Etiology, 0.6, full), sparse_result("Sparse", Etiology, 0.6, no_treatment)],
+            vec![sparse_result("Full", Manifestation, 0.6, full), sparse_result("Sparse", Manifestation, 0.6, no_treatment)],
+            vec![sparse_result("Full", Treatment, 0.6, full)],
+            &weights,
+            0.0,
+            10,
+        );
+        assert!((ranked[0].score - ranked[1].score).abs() < 1e-5, "{} vs {}", ranked[0].score, ranked[1].score);
+        
+        // One matching section doesn't beat a condition that matched in all four tables
+        let ranked = rank(
+            vec![sparse_result("Full", Description, 0.5, full)],
+            vec![sparse_result("Full", Etiology, 0.5, full)],
+            vec![sparse_result("Full", Manifestation, 0.5, full), sparse_result("Sparse", Manifestation, 0.6, symptoms_only)],
+            vec![sparse_result("Full", Treatment, 0.5, full)],
+            &weights,
+            0.0,
+            10,
+        );
+        assert_eq!(names(&ranked), ["Full", "Sparse"]);
+        assert!((ranked[1].score - 0.6 * weights.manifestation * MAX_SPARSE_SCALE).abs() < 1e-5);
+        
+        // But it still beats a full condition that matched only the same section, equally well
+        let ranked = rank(
+            Vec::new(),
+            Vec::new(),
+            vec![sparse_result("Full", Manifestation, 0.6, full), sparse_result("Sparse", Manifestation, 0.6, symptoms_only)],
+            Vec::new(),
+            &weights,
+            0.0,
+            10,
+        );
+        assert_eq!(names(&ranked), ["Sparse", "Full"]);
+    }
+    
+    #[test]
+    fn each_text_comes_from_its_own_table() {
+        let ranked = rank(
+            vec![result("Asthma", EmbeddingType::Description, 0.5), result("Gout", EmbeddingType::Description, 0.4)],
+            vec![result("Gout", EmbeddingType::Etiology, 0.5)],
+            vec![result("Asthma", EmbeddingType::Manifestation, 0.5)],
+            vec![result("Asthma", EmbeddingType::Treatment, 0.5)],
+            &Weights::default(),
+            0.0,
+            10,
+        );
+        
+        let asthma = ranked.iter().find(|c| c.name == "Asthma").unwrap();
+        assert_eq!(asthma.description_text.as_deref(), Some("description text for Asthma"));
+        assert_eq!(asthma.etiology_text, None);
+        assert_eq!(asthma.manifestation_text.as_deref(), Some("manifestation text for Asthma"));
+        assert_eq!(asthma.treatment_text.as_deref(), Some("treatment text for Asthma"));
+        
+        let gout = ranked.iter().find(|c| c.name == "Gout").unwrap();
+        assert_eq!(gout.description_text.as_deref(), Some("description text for Gout"));
+        assert_eq!(gout.etiology_text.as_deref(), Some("etiology text for Gout"));
+        assert_eq!((gout.manifestation_text.as_deref(), gout.treatment_text.as_deref()), (None, None));
+    }
+    
+    #[tokio::test]
+    async fn zero_or_nan_query_stops_the_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = crate::embedding::open_db(dir.path()).await.unwrap();
+        let weights = Weights::default();
+        let options = SearchOptions {
+            top_k_per_table: 5,
+            num_results: 5,
+            min_score: 0.0,
+            group: None,
+            allowed: None,
+            weights: &weights,
+            metric: Metric::Cosine,
+            created_after: None,
+        };
+        
+        for degenerate in [vec![0.0; 8], vec![0.5, f32::NAN, 0.1], vec![f32::INFINITY, 0.0]] {
+            let error = cross_reference_search(&db, degenerate.clone(), &options, &|_, _| panic!("searched a table"))
+                .await
+                .unwrap_err();
+            assert!(error.to_string().contains("try rewording it"), "{:?}: {}", degenerate, error);
+        }
+    }
+    
+    #[test]
+    fn truncate_chars_stops_on_a_char_boundary() {
+        // 99 ASCII bytes, then "é" occupies bytes 99 and 100, so byte 100 is mid-character
+        let text = format!("{}é and more text after it", "a".repeat(99));
+        assert!(!text.is_char_boundary(100));
+        
+        let truncated = truncate_chars(&text, 100);
+        assert_eq!(truncated.chars().count(), 100);
+        assert!(truncated.ends_with('é'));
+        assert_eq!(truncate_chars("short", 100), "short");
+        assert_eq!(truncate_chars("°C – 40°", 3), "°C ");
+    }
+    
+    #[test]
+    fn preprocessing_strips_filler_and_uses_clinical_terms() {
+        let synonyms = SynonymMap::default();
+        let cases = [
+            ("I have a bad headache and feel dizzy", "a bad headache and dizziness"),
+            ("I've been throwing up and I'm really tired lately", "vomiting and fatigue"),
+            ("My stomach ache won't go away!", "abdominal pain won't go away"),
+            ("Runny nose, sore throat", "nasal discharge, pharyngitis"),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(preprocess_query(query, &synonyms), expected, "preprocessing {:?}", query);
+        }
+    }
+    
+    #[test]
+    fn synonyms_expand_whole_words_only() {
+        let synonyms = SynonymMap::default();
+        assert_eq!(synonyms.expand("Tummy ache after I threw up"), "abdominal pain after i vomiting");
+        assert_eq!(synonyms.expand("It hurts to pee"), "it hurts to urination");
+        assert_eq!(synonyms.expand("Runny nose, sore throat."), "nasal discharge, pharyngitis");
+        // Words that only contain a phrase are left alone
+        assert_eq!(synonyms.expand("peeling skin and a tiredness"), "peeling skin and a tiredness");
+    }
+    
+    #[test]
+    fn adjacent_phrases_are_all_expanded() {
+        let synonyms = SynonymMap::default();
+        assert_eq!(synonyms.expand("dizzy tired itchy"), "dizziness fatigue itching");
+        assert_eq!(synonyms.expand("throwing up throwing up"), "vomiting vomiting");
+        assert_eq!(synonyms.expand("pee pee, poop"), "urination urination, stool");
+        assert_eq!(
+            synonyms.matches("dizzy tired dizzy"),
+            [("dizzy", "dizziness"), ("tired", "fatigue")]
+        );
+    }
+    
+    #[test]
+    fn longest_phrase_wins_where_phrases_overlap() {
+        let synonyms = SynonymMap::parse("up = upward\nthrowing up = vomiting\nthrowing = tossing\n");
+        assert_eq!(synonyms.expand("throwing up, then throwing"), "vomiting, then tossing");
+    }
+    
+    #[test]
+    fn query_of_only_filler_is_kept_as_typed() {
+        assert_eq!(preprocess_query("  I feel really  ", &SynonymMap::default()), "I feel really");
+    }
 }
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::engine::validate_query;
+use crate::response::SearchResponse;
+use crate::{status, SearchEngine};
+
+/// State shared by every request handler
+#[derive(Clone)]
+struct AppState {
+    engine: Arc<SearchEngine>,
+    num_results: usize,
+}
+
+/// Query string for `/search`
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+    n: Option<usize>,
+}
+
+/// Serve `/search` and `/health` on the given port until the process is stopped
+pub async fn serve(engine: SearchEngine, port: u16, num_results: usize) -> Result<()> {
+    let state = AppState {
+        engine: Arc::new(engine),
+        num_results,
+    };
+    
+    let app = Router::new()
+        .route("/search", get(search))
+        .route("/health", get(health))
+        .with_state(state);
+    
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    status!("Listening on http://{}", addr);
+    
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// Run a search and return the ranked conditions as JSON
+async fn search(State(state): State<AppState>, Query(params): Query<SearchParams>) -> Response {
+    let query = params.q.trim();
+    if let Err(e) = validate_query(query) {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    
+    let num_results = params.n.unwrap_or(state.num_results);
+    if num_results == 0 {
+        return (StatusCode::BAD_REQUEST, "n must be at least 1").into_response();
+    }
+    match state.engine.search(query, num_results).await {
+        Ok(results) => Json(SearchResponse::new(query, Some(state.engine.model_choice()), &results)).into_response(),
+        Err(e) => {
+            log::error!("Search error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Search error: {}", e)).into_response()
+        }
+    }
+}
+
+/// Report whether the database is ready to answer searches
+async fn health(State(state): State<AppState>) -> StatusCode {
+    if state.engine.has_embeddings().await {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::Result;
+use console::{measure_text_width, style, truncate_str, Key, Term};
+use log::warn;
+use std::path::Path;
+use tokio::task;
+
+use crate::engine::validate_query;
+use crate::fetch::{is_meaningful, ConditionData};
+use crate::search::RankedCondition;
+use crate::{history, ui, SearchEngine};
+
+/// Rows taken by the search box, status line, separators and key help
+const CHROME_ROWS: usize = 5;
+
+/// What the full-screen search is showing
+struct App {
+    query: String,
+    status: String,
+    results: Vec<RankedCondition>,
+    /// Full sections of each result, in the same order
+    details: Vec<ConditionData>,
+    selected: usize,
+    /// First line of the detail pane that is shown
+    scroll: usize,
+}
+
+impl App {
+    fn new() -> Self {
+        Self {
+            query: String::new(),
+            status: "Describe your symptoms and press Enter".to_string(),
+            results: Vec::new(),
+            details: Vec::new(),
+            selected: 0,
+            scroll: 0,
+        }
+    }
+    
+    /// Rows available to the detail pane on a terminal `height` rows tall
+    fn detail_rows(&self, height: usize) -> usize {
+        height.saturating_sub(CHROME_ROWS + self.results.len().max(1))
+    }
+}
+
+/// Run the full-screen search until Esc or Ctrl-C: a search box, results picked with the arrow keys,
+/// and the highlighted condition's full sections; `details` looks up those sections for a result
+pub async fn run(
+    engine: &SearchEngine,
+    num_results: usize,
+    history_dir: Option<&Path>,
+    details: impl Fn(&RankedCondition) -> ConditionData,
+) -> Result<()> {
+    let term = Term::buffered_stdout();
+    term.hide_cursor()?;
+    term.clear_screen()?;
+    let outcome = event_loop(&term, engine, num_results, history_dir, &details).await;
+    term.clear_screen()?;
+    term.show_cursor()?;
+    term.flush()?;
+    outcome
+}
+
+/// Handle keys until the user leaves, searching whenever Enter is pressed
+async fn event_loop(
+    term: &Term,
+    engine: &SearchEngine,
+    num_results: usize,
+    history_dir: Option<&Path>,
+    details: &impl Fn(&RankedCondition) -> ConditionData,
+) -> Result<()> {
+    let mut app = App::new();
+    loop {
+        draw(term, &mut app)?;
+        let reader = term.clone();
+        let key = task::spawn_blocking(move || reader.read_key_raw()).await??;
+        let height = term.size().0 as usize;
+        match key {
+            Key::Escape | Key::CtrlC => return Ok(()),
+            Key::Enter => {
+                let query = app.query.trim().to_string();
+                if let Err(e) = validate_query(&query) {
+                    app.status = e.to_string();
+                    continue;
+                }
+    
+                app.status = "Searching...".to_string();
+                draw(term, &mut app)?;
+                match engine.search(&query, num_results).await {
+                    Ok(results) => {
+                        if let Some(dir) = history_dir {
+                            if let Err(e) = history::append(dir, &query, &results) {
+                                warn!("Could not save search history: {}", e);
+                            }
+                        }
+                        app.status = format!("{} conditions for \"{}\"", results.len(), query);
+                        app.details = results.iter().map(details).collect();
+                        app.results = results;
+                        app.selected = 0;
+                        app.scroll = 0;
+                    }
+                    Err(e) => app.status = format!("Search failed: {}", e),
+                }
+            }
+            Key::ArrowUp if app.selected > 0 => {
+                app.selected -= 1;
+                app.scroll = 0;
+            }
+            Key::ArrowDown if app.selected + 1 < app.results.len() => {
+                app.selected += 1;
+                app.scroll = 0;
+            }
+            Key::PageDown => app.scroll += app.detail_rows(height).max(1),
+            Key::PageUp => app.scroll = app.scroll.saturating_sub(app.detail_rows(height).max(1)),
+            Key::Backspace => {
+                app.query.pop();
+            }
+            Key::Char(c) if !c.is_control() => app.query.push(c),
+            _ => {}
+        }
+    }
+}
+
+/// Redraw the whole screen, keeping the detail scroll within the highlighted condition's text
+fn draw(term: &Term, app: &mut App) -> Result<()> {
+    let (height, width) = term.size();
+    let (height, width) = (height as usize, width as usize);
+    // One column short of the edge so a full line never wraps onto the next
+    let width = width.saturating_sub(1);
+    let rule = "─".repeat(width);
+    
+    let mut lines = vec![
+        format!("Search: {}{}", app.query, style(" ").reverse()),
+        style(app.status.as_str()).dim().to_string(),
+        rule.clone(),
+    ];
+    if app.results.is_empty() {
+        lines.push(style("No results yet").dim().to_string());
+    }
+    for (i, result) in app.results.iter().enumerate() {
+        // The highlight is one style run, so the selected row's score isn't colored
+        if i == app.selected {
+            let line = format!("{:>2}. {}  {:.2}", i + 1, result.name, result.score);
+            lines.push(format!("{} {}", style("›").cyan().bold(), style(line).reverse()));
+        } else {
+            lines.push(format!("  {:>2}. {}  {}", i + 1, ui::condition_name(&result.name), ui::score(result.score)));
+        }
+    }
+    lines.push(rule);
+    
+    let detail = app
+        .details
+        .get(app.selected)
+        .map(|condition| detail_lines(condition, width))
+        .unwrap_or_default();
+    let rows = app.detail_rows(height);
+    app.scroll = app.scroll.min(detail.len().saturating_sub(rows));
+    lines.extend(detail.into_iter().skip(app.scroll).take(rows));
+    while lines.len() < height.saturating_sub(1) {
+        lines.push(String::new());
+    }
+    lines.push(
+        style("↑/↓ select · PgUp/PgDn scroll · Enter search · Esc quit · Not a diagnosis; consult a medical professional")
+            .dim()
+            .to_string(),
+    );
+    
+    term.move_cursor_to(0, 0)?;
+    for (i, line) in lines.iter().take(height).enumerate() {
+        term.clear_line()?;
+        term.write_str(&truncate_str(line, width, ""))?;
+        if i + 1 < height {
+            term.write_str("\n")?;
+        }
+    }
+    term.flush()?;
+    Ok(())
+}
+
+/// A condition's sections, groups and link, word-wrapped to `width` columns
+fn detail_lines(condition: &ConditionData, width: usize) -> Vec<String> {
+    let mut lines = vec![ui::condition_name(&condition.name).to_string()];
+    let sections = [
+        ("Description", &condition.description),
+        ("Etiology", &condition.etiology),
+        ("Manifestations", &condition.manifestations),
+        ("Treatments", &condition.treatments),
+    ];
+    for (heading, text) in sections {
+        lines.push(String::new());
+        lines.push(style(heading).bold().to_string());
+        if is_meaningful(text) {
+            lines.extend(wrap(text, width));
+        } else {
+            lines.push("(no text)".to_string());
+        }
+    }
+    if !condition.groups.is_empty() {
+        lines.push(String::new());
+        lines.extend(wrap(&format!("Groups: {}", condition.groups.join(", ")), width));
+    }
+    if !condition.medline_url.is_empty() {
+        lines.push(format!("More info: {}", condition.medline_url));
+    }
+    lines
+}
+
+/// Break plain text into lines of at most `width` columns at whitespace
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && measure_text_width(&line) + 1 + measure_text_width(word) > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
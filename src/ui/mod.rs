@@ -1,12 +1,137 @@
-use std::io;
+use chrono::{DateTime, Utc};
+use console::{style, StyledObject};
+use log::{info, LevelFilter};
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-/// Get user input for their symptoms/issues
-pub fn get_user_input() -> String {
-    println!("\n═══════════════════════════════════════════════════════════════");
-    println!("              DESCRIBE YOUR SYMPTOMS OR CONCERNS");
-    println!("═══════════════════════════════════════════════════════════════");
-    println!("Enter what you're feeling or experiencing:\n");
+use crate::embedding::{DatabaseStats, SearchResult};
+use crate::fetch::{is_meaningful, ConditionData, DryRunReport};
+use crate::search::Confidence;
+
+/// Matches listed per table while a search is in progress
+const TABLE_HITS_SHOWN: usize = 3;
+
+/// Whether status output is redirected away from stdout
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Send status and progress messages to stderr so stdout only carries results
+pub fn set_status_to_stderr(enabled: bool) {
+    STATUS_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+/// Check whether status messages currently go to stderr
+pub fn status_to_stderr() -> bool {
+    STATUS_TO_STDERR.load(Ordering::Relaxed)
+}
+
+/// Send log messages to stderr: warnings and errors by default, progress with -v, step-by-step detail with -vv
+pub fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        _ => LevelFilter::Debug,
+    };
+    // RUST_LOG still overrides the flag
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format_timestamp(None)
+        .format_target(false)
+        .parse_default_env()
+        .init();
+}
+
+/// Turn colored output on unless disabled by flag, `NO_COLOR`, or a non-terminal stdout
+pub fn init_color(no_color: bool) {
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let enabled = !no_color && !no_color_env && io::stdout().is_terminal();
+    console::set_colors_enabled(enabled);
+    console::set_colors_enabled_stderr(enabled);
+}
+
+/// Condition name as shown in results
+pub fn condition_name(name: &str) -> StyledObject<&str> {
+    style(name).bold()
+}
+
+/// Score graded green, yellow, or red by how confident the match is
+pub fn score(score: f32) -> StyledObject<String> {
+    let text = style(format!("{:.2}", score));
+    if score >= 2.0 {
+        text.green()
+    } else if score >= 1.0 {
+        text.yellow()
+    } else {
+        text.red()
+    }
+}
+
+/// Match strength label colored by its confidence band
+pub fn confidence(label: String, confidence: Confidence) -> StyledObject<String> {
+    let text = style(label);
+    match confidence {
+        Confidence::Strong => text.green(),
+        Confidence::Possible => text.yellow(),
+        Confidence::Weak => text.red(),
+    }
+}
+
+/// A matched query term in a snippet, bold and underlined, or wrapped in `**` without color
+pub fn highlight(term: &str) -> String {
+    if console::colors_enabled() {
+        style(term).bold().underlined().to_string()
+    } else {
+        format!("**{}**", term)
+    }
+}
+
+/// Medical disclaimer text
+pub fn disclaimer<D>(text: D) -> StyledObject<D> {
+    style(text).yellow().bold()
+}
+
+/// Print a status line to stdout, or to stderr when results are being piped
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::ui::status_to_stderr() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Get user input for their symptoms/issues, joining lines until a blank line or `.` in multiline mode
+pub fn get_user_input(multiline: bool) -> String {
+    status!("\n═══════════════════════════════════════════════════════════════");
+    status!("              DESCRIBE YOUR SYMPTOMS OR CONCERNS");
+    status!("═══════════════════════════════════════════════════════════════");
+    if multiline {
+        status!("Enter what you're feeling or experiencing (finish with a blank line or '.'):\n");
+    } else {
+        status!("Enter what you're feeling or experiencing:\n");
+    }
+    
+    let first = read_line();
+    // An empty or `q` first line still means quit
+    if !multiline || first.is_empty() || first.eq_ignore_ascii_case("q") {
+        return first;
+    }
+    
+    let mut lines = vec![first];
+    loop {
+        let line = read_line();
+        if line.is_empty() || line == "." {
+            break;
+        }
+        lines.push(line);
+    }
     
+    lines.join(" ")
+}
+
+/// Read one trimmed line from stdin, empty at end of input
+fn read_line() -> String {
     let mut input = String::new();
     
     io::stdin()
@@ -18,45 +143,180 @@ pub fn get_user_input() -> String {
 
 /// Display welcome message
 pub fn display_welcome() {
-    println!();
-    println!("╔═══════════════════════════════════════════════════════════════╗");
-    println!("║                        Shiroyaku                              ║");
-    println!("║            MedlinePlus Symptom Search Engine                  ║");
-    println!("╠═══════════════════════════════════════════════════════════════╣");
-    println!("║  This tool helps find relevant medical conditions based on    ║");
-    println!("║  your symptoms. It uses vector embeddings to search through   ║");
-    println!("║  medical information from MedlinePlus.                        ║");
-    println!("║                                                               ║");
-    println!("║          WARNING: This is NOT a diagnosis tool.               ║");
-    println!("║     Always consult a medical professional for proper          ║");
-    println!("║     diagnosis and treatment.                                  ║");
-    println!("╚═══════════════════════════════════════════════════════════════╝");
-    println!();
+    status!();
+    status!("╔═══════════════════════════════════════════════════════════════╗");
+    status!("║                        {}                              ║", style("Shiroyaku").bold());
+    status!("║            MedlinePlus Symptom Search Engine                  ║");
+    status!("╠═══════════════════════════════════════════════════════════════╣");
+    status!("║  This tool helps find relevant medical conditions based on    ║");
+    status!("║  your symptoms. It uses vector embeddings to search through   ║");
+    status!("║  medical information from MedlinePlus.                        ║");
+    status!("║                                                               ║");
+    status!("║          {}               ║", disclaimer("WARNING: This is NOT a diagnosis tool."));
+    status!("║     {}          ║", disclaimer("Always consult a medical professional for proper"));
+    status!("║     {}                                  ║", disclaimer("diagnosis and treatment."));
+    status!("╚═══════════════════════════════════════════════════════════════╝");
+    status!();
 }
 
 /// Display loading message for data fetching
 pub fn display_fetching_message() {
-    println!("Fetching latest MedlinePlus data...");
+    info!("Fetching latest MedlinePlus data...");
+}
+
+/// Display loading message for a forced rebuild
+pub fn display_rebuilding_message() {
+    info!("Rebuilding embeddings from cached MedlinePlus data...");
 }
 
 /// Display loading message for embedding
 pub fn display_embedding_message() {
-    println!("Processing embeddings (this may take a while on first run)...");
+    info!("Processing embeddings (this may take a while on first run)...");
+}
+
+/// Display a table's best matches while the other tables are still being searched
+pub fn display_table_hits(table: &str, hits: &[SearchResult]) {
+    let names: Vec<&str> = hits.iter().take(TABLE_HITS_SHOWN).map(|h| h.condition_name.as_str()).collect();
+    if names.is_empty() {
+        status!("  {:<14} no matches", table);
+    } else {
+        status!("  {:<14} {}", table, names.join(", "));
+    }
+}
+
+/// Display what the embedding database holds
+pub fn display_stats(stats: &DatabaseStats) {
+    println!("\n═══════════════════════════════════════════════════════════════");
+    println!("{:^63}", "DATABASE STATS");
+    println!("═══════════════════════════════════════════════════════════════\n");
+    
+    println!("  Location:    {}", stats.path.display());
+    println!("  Size:        {:.1} MB", stats.size_bytes as f64 / (1024.0 * 1024.0));
+    match &stats.marker {
+        Some(marker) => {
+            println!("  Model:       {} ({} dims)", marker.model, marker.dimension);
+            println!("  Language:    {}", marker.language);
+            println!("  Storage:     v{}", marker.storage_version);
+        }
+        None => println!("  Model:       unknown (no version marker; the database will be rebuilt)"),
+    }
+    println!("  Conditions:  {}\n", stats.distinct_conditions);
+    
+    let time = |t: Option<DateTime<Utc>>| t.map_or("-".to_string(), |t| t.format("%Y-%m-%d %H:%M").to_string());
+    println!("  {:<26} {:>8} {:>10} {:>17} {:>17}", "Table", "Rows", "Dimension", "Oldest", "Newest");
+    for table in &stats.tables {
+        let dimension = table.dimension.map_or("-".to_string(), |d| d.to_string());
+        let rows = if table.exists { table.rows.to_string() } else { "missing".to_string() };
+        println!(
+            "  {:<26} {:>8} {:>10} {:>17} {:>17}",
+            table.name,
+            rows,
+            dimension,
+            time(table.oldest),
+            time(table.newest)
+        );
+    }
+    println!();
+}
+
+/// Print the counts from a fetch dry run
+pub fn display_dry_run(report: &DryRunReport) {
+    println!("\n═══════════════════════════════════════════════════════════════");
+    println!("{:^63}", "DRY RUN");
+    println!("═══════════════════════════════════════════════════════════════\n");
+    
+    println!("  Topics parsed:        {}", report.topics_parsed);
+    println!("  Kept by the filter:   {} ({} duplicates dropped)", report.topics_kept, report.duplicates);
+    println!("  With a description:   {}", report.descriptions);
+    println!("  With etiology:        {}", report.etiologies);
+    println!("  With manifestations:  {}", report.manifestations);
+    println!("  With treatments:      {}", report.treatments);
+    println!("  Embeddings to create: {}", report.embeddings());
+    println!();
+}
+
+/// Display every section of a condition in full
+pub fn display_details(condition: &ConditionData) {
+    println!("\n═══════════════════════════════════════════════════════════════");
+    println!("{:^63}", condition.name.to_uppercase());
+    println!("═══════════════════════════════════════════════════════════════");
+    
+    let sections = [
+        ("Description", &condition.description),
+        ("Etiology", &condition.etiology),
+        ("Manifestations", &condition.manifestations),
+        ("Treatments", &condition.treatments),
+    ];
+    for (heading, text) in sections {
+        println!("\n{}", style(heading).bold());
+        if is_meaningful(text) {
+            println!("{}", text);
+        } else {
+            println!("(no text)");
+        }
+    }
+    if !condition.groups.is_empty() {
+        println!("\nGroups: {}", condition.groups.join(", "));
+    }
+    if !condition.medline_url.is_empty() {
+        println!("More info: {}", condition.medline_url);
+    }
+    println!();
+}
+
+/// Display loading message for embedding fresh cached data
+pub fn display_embedding_cached_message() {
+    info!("Embedding cached MedlinePlus data (no download needed)...");
 }
 
 /// Display when skipping update
 pub fn display_skipping_update() {
-    println!("Skipping data update (using existing embeddings)");
+    info!("Skipping data update (using existing embeddings)");
 }
 
 /// Display initialization message
 pub fn display_initializing() {
-    print!("Initializing embedding database... ");
+    info!("Initializing embedding database...");
+}
+
+/// Ask a yes/no question, treating anything but "y" or "yes" as no
+pub fn confirm(question: &str) -> bool {
+    status!("\n{} (y/n)", question);
+    let answer = read_line().to_lowercase();
+    answer == "y" || answer == "yes"
+}
+
+/// Ask for a line of text, empty when nothing was entered
+pub fn ask_text(question: &str) -> String {
+    status!("\n{}", question);
+    read_line()
+}
+
+/// What to do after a set of results has been shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NextAction {
+    /// Describe new symptoms
+    NewSearch,
+    /// Find conditions similar to the result with this 1-based number
+    MoreLike(usize),
+    /// Show the full text of the result with this 1-based number
+    Details(usize),
+    /// Save the results under a name
+    Save,
+    /// Leave the search
+    Quit,
 }
 
-/// Ask user if they want to search again
-pub fn ask_search_again() -> bool {
-    println!("\nWould you like to search for another symptom? (y/n)");
+/// Ask user whether to search again, explore, read or save the `num_results` results, or quit
+pub fn ask_next_action(num_results: usize) -> NextAction {
+    if num_results > 0 {
+        status!(
+            "\nEnter a result number (1-{}) to find conditions like it, 'd <number>' to read its full text, 's' to save these results, or search for another symptom? (y/n)",
+            num_results
+        );
+    } else {
+        status!("\nWould you like to search for another symptom? (y/n)");
+    }
     
     let mut input = String::new();
     io::stdin()
@@ -64,5 +324,19 @@ pub fn ask_search_again() -> bool {
         .expect("Failed to read user input");
     
     let input = input.trim().to_lowercase();
-    input == "y" || input == "yes"
+    // "d 2" or "details 2"; the full text is only worth printing for someone reading along
+    let details = input
+        .strip_prefix("details")
+        .or_else(|| input.strip_prefix('d'))
+        .and_then(|n| n.trim().parse::<usize>().ok())
+        .filter(|n| (1..=num_results).contains(n) && io::stdin().is_terminal());
+    if let Some(n) = details {
+        return NextAction::Details(n);
+    }
+    match input.parse::<usize>() {
+        Ok(n) if (1..=num_results).contains(&n) => NextAction::MoreLike(n),
+        _ if input == "y" || input == "yes" => NextAction::NewSearch,
+        _ if num_results > 0 && (input == "s" || input == "save") => NextAction::Save,
+        _ => NextAction::Quit,
+    }
 }
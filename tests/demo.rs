@@ -0,0 +1,98 @@
+//! The bundled demo topics taken through fetch, embedding and search, without reaching MedlinePlus
+
+use anyhow::Result;
+use shiroyaku::embedding::{Embed, EmbedderHandle, ExecutionProvider, ModelChoice, VersionMarker};
+use shiroyaku::fetch::{self, is_meaningful, ConditionData, TopicFilter};
+use shiroyaku::SearchEngine;
+
+/// Bag-of-words embedder: every word adds one to a coordinate picked by its hash, so texts
+/// sharing words point the same way. Deterministic and needs no model download.
+struct WordEmbedder {
+    dimension: usize,
+}
+
+impl Embed for WordEmbedder {
+    fn embed_texts(&mut self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let embed = |text: &str| {
+            let mut vector = vec![0.0; self.dimension];
+            for word in text.to_lowercase().split(|c: char| !c.is_alphanumeric()).filter(|w| w.len() > 3) {
+                let hash = u64::from_str_radix(&fetch::stable_hash([word]), 16).unwrap_or_default();
+                vector[hash as usize % self.dimension] += 1.0;
+            }
+            vector
+        };
+        Ok(texts.iter().map(|t| embed(t)).collect())
+    }
+}
+
+fn demo_conditions(data_dir: &std::path::Path) -> Vec<ConditionData> {
+    fetch::load_fixture(data_dir, "en", &TopicFilter::default()).expect("demo topics parse")
+}
+
+#[test]
+fn demo_topics_go_through_the_fetch_pipeline() {
+    let dir = tempfile::tempdir().unwrap();
+    let conditions = demo_conditions(dir.path());
+    
+    let names: Vec<&str> = conditions.iter().map(|c| c.name.as_str()).collect();
+    for name in ["Influenza Infection", "Migraine Disorder", "Iron Deficiency Anemia", "Type 2 Diabetes"] {
+        assert!(names.contains(&name), "{} missing from {:?}", name, names);
+    }
+    let influenza = conditions.iter().find(|c| c.name == "Influenza Infection").unwrap();
+    assert!(influenza.manifestations.starts_with("Symptoms include a sudden fever"));
+    assert!(influenza.treatments.starts_with("Treatment is rest and fluids"));
+    assert_eq!(influenza.groups, ["Infections"]);
+    assert!(conditions.iter().all(|c| is_meaningful(&c.description)));
+    
+    // Cached like a download, so a second run sees the source as unchanged
+    assert!(fetch::source_hash(dir.path(), "en").is_some());
+}
+
+#[tokio::test]
+async fn demo_search_ranks_the_described_condition_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let conditions = demo_conditions(dir.path());
+    let model_choice = ModelChoice::default();
+    let dimension = VersionMarker::current(model_choice, "en").unwrap().dimension;
+    let model = EmbedderHandle::spawn(WordEmbedder { dimension }).unwrap();
+    let engine = SearchEngine::with_embedder(dir.path(), model_choice, model).await.unwrap();
+    
+    engine.embed_conditions(conditions).await.unwrap();
+    assert!(engine.has_embeddings().await);
+    
+    let results = engine.search("throbbing head pain, nausea and sensitivity to light", 3).await.unwrap();
+    assert_eq!(results[0].name, "Migraine Disorder");
+    assert!(results[0].manifestation_text.as_deref().unwrap_or_default().contains("throbbing"));
+    
+    let results = engine.search("iron supplements for pale skin and weakness", 3).await.unwrap();
+    assert_eq!(results[0].name, "Iron Deficiency Anemia");
+}
+
+#[tokio::test]
+async fn demo_search_expands_abbreviations_to_condition_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let conditions = demo_conditions(dir.path());
+    let model_choice = ModelChoice::default();
+    let dimension = VersionMarker::current(model_choice, "en").unwrap().dimension;
+    let model = EmbedderHandle::spawn(WordEmbedder { dimension }).unwrap();
+    let engine = SearchEngine::with_embedder(dir.path(), model_choice, model).await.unwrap();
+    engine.embed_conditions(conditions).await.unwrap();
+    
+    assert_eq!(engine.resolved_aliases("GERD"), [("gerd", "gastroesophageal reflux disease")]);
+    // "gerd" alone shares no word with any summary, so only the expansion can find the condition
+    let results = engine.search("GERD", 3).await.unwrap();
+    assert_eq!(results[0].name, "Gastroesophageal Reflux Disease");
+}
+
+#[tokio::test]
+#[ignore = "downloads the embedding model"]
+async fn demo_search_with_the_real_model() {
+    let dir = tempfile::tempdir().unwrap();
+    let conditions = demo_conditions(dir.path());
+    let engine = SearchEngine::open(dir.path(), ModelChoice::default(), ExecutionProvider::default()).await.unwrap();
+    
+    engine.embed_conditions(conditions).await.unwrap();
+    
+    let results = engine.search("sudden fever, chills and a dry cough", 3).await.unwrap();
+    assert_eq!(results[0].name, "Influenza Infection");
+}